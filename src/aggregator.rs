@@ -1,18 +1,65 @@
-use std::collections::HashMap;
-use std::str::FromStr;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
 
-#[derive(Debug)]
+use crate::line_parser::{quote_path, LineKind, ParsedLine};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct FileMeta {
-    pub no_newline_count: usize,
+    // Started by a plain unified "---" line, so "+++" names the new file.
+    pub plain: bool,
+    // The paths are known for certain, from an unambiguous "diff --git" line or
+    // from rename or copy lines, so "---"/"+++" must name them exactly.
+    pub exact_paths: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
 pub enum DiffAction {
     Delete,
     Add,
     Context,
 }
 
+impl DiffAction {
+    /// The column character that marks the action in a diff: `-`, `+` or a space.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DiffAction::Delete => "-",
+            DiffAction::Add => "+",
+            DiffAction::Context => " ",
+        }
+    }
+
+    /// The column character as a `char`: `'-'`, `'+'` or `' '`.
+    pub fn as_char(&self) -> char {
+        match self {
+            DiffAction::Delete => '-',
+            DiffAction::Add => '+',
+            DiffAction::Context => ' ',
+        }
+    }
+
+    // The action that undoes this one.
+    fn inverse(&self) -> DiffAction {
+        match self {
+            DiffAction::Delete => DiffAction::Add,
+            DiffAction::Add => DiffAction::Delete,
+            DiffAction::Context => DiffAction::Context,
+        }
+    }
+}
+
+impl fmt::Display for DiffAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 impl FromStr for DiffAction {
     type Err = String;
 
@@ -26,285 +73,2025 @@ impl FromStr for DiffAction {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ChunkDiffLine {
+    // On the side a line is missing from, the number of the next line there.
     pub from_line_number: usize,
     pub to_line_number: usize,
     pub line: String,
     pub action: DiffAction,
+    // In combined diffs, the line's action against each parent, one per column;
+    // `action` then sums them up. Empty for ordinary two-sided diffs.
+    pub parent_actions: Vec<DiffAction>,
+    // The line ended in "\r\n". `line` only keeps the `\r` with
+    // `ParserConfig::preserve_cr`.
+    pub trailing_cr: bool,
 }
 
-#[derive(Debug, Clone)]
+impl ChunkDiffLine {
+    /// The line as it appears in the diff, led by its action column (one per
+    /// parent in combined diffs), without the line ending.
+    pub fn with_prefix(&self) -> String {
+        let actions = if self.parent_actions.is_empty() {
+            core::slice::from_ref(&self.action)
+        } else {
+            &self.parent_actions
+        };
+        let mut line: String = actions.iter().map(DiffAction::as_str).collect();
+        line.push_str(&self.line);
+        line
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LinePoint {
     pub line_start: usize,
     pub line_count: usize,
 }
 
-#[derive(Debug, Clone)]
+impl LinePoint {
+    // An empty range (count 0) contains no line.
+    fn contains(&self, n: usize) -> bool {
+        (self.line_start..self.line_start + self.line_count).contains(&n)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ChunkDiff {
     pub from: LinePoint,
     pub to: LinePoint,
+    // Every parent's range for combined (merge) diffs, where `from` is the
+    // first parent. Empty for ordinary two-sided diffs.
+    pub parents: Vec<LinePoint>,
+    pub section_heading: Option<String>,
     pub lines: Vec<ChunkDiffLine>,
 }
 
-#[derive(Debug)]
+impl ChunkDiff {
+    /// The hunk as it was before the change: deleted and context lines.
+    pub fn old_lines(&self) -> Vec<&str> {
+        self.lines_where(|action| !matches!(action, DiffAction::Add))
+    }
+
+    /// The hunk as it is after the change: added and context lines.
+    pub fn new_lines(&self) -> Vec<&str> {
+        self.lines_where(|action| !matches!(action, DiffAction::Delete))
+    }
+
+    /// Whether line `n` of the old file falls within this chunk's `from` range.
+    pub fn contains_old_line(&self, n: usize) -> bool {
+        self.from.contains(n)
+    }
+
+    /// Whether line `n` of the new file falls within this chunk's `to` range.
+    pub fn contains_new_line(&self, n: usize) -> bool {
+        self.to.contains(n)
+    }
+
+    /// Number of unchanged lines before the first change.
+    pub fn leading_context(&self) -> usize {
+        self.lines
+            .iter()
+            .take_while(|line| matches!(line.action, DiffAction::Context))
+            .count()
+    }
+
+    /// Number of unchanged lines after the last change.
+    pub fn trailing_context(&self) -> usize {
+        self.lines
+            .iter()
+            .rev()
+            .take_while(|line| matches!(line.action, DiffAction::Context))
+            .count()
+    }
+
+    /// Whether the hunk adds lines without deleting any. A hunk of only context
+    /// lines changes nothing, so it is neither a pure addition nor a pure deletion.
+    pub fn is_pure_addition(&self) -> bool {
+        self.has_action(DiffAction::Add) && !self.has_action(DiffAction::Delete)
+    }
+
+    /// Whether the hunk deletes lines without adding any; see [`Self::is_pure_addition`].
+    pub fn is_pure_deletion(&self) -> bool {
+        self.has_action(DiffAction::Delete) && !self.has_action(DiffAction::Add)
+    }
+
+    fn has_action(&self, action: DiffAction) -> bool {
+        self.lines.iter().any(|line| line.action == action)
+    }
+
+    // The chunk's changes with at most `n` context lines around them, one chunk
+    // per group of changes. Combined chunks are left whole.
+    fn limit_context(self, n: usize) -> Vec<ChunkDiff> {
+        if !self.parents.is_empty() {
+            return vec![self];
+        }
+        // (first, last) index of each group of changes at most 2n lines apart.
+        let mut groups: Vec<(usize, usize)> = vec![];
+        let changes = self
+            .lines
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| !matches!(line.action, DiffAction::Context))
+            .map(|(idx, _)| idx);
+        for idx in changes {
+            match groups.last_mut() {
+                Some((_, last)) if idx - *last - 1 <= 2 * n => *last = idx,
+                _ => groups.push((idx, idx)),
+            }
+        }
+
+        groups
+            .into_iter()
+            .map(|(first, last)| {
+                let end = (last + n + 1).min(self.lines.len());
+                let lines = self.lines[first.saturating_sub(n)..end].to_vec();
+                let old = lines
+                    .iter()
+                    .filter(|line| !matches!(line.action, DiffAction::Add))
+                    .count();
+                let new = lines
+                    .iter()
+                    .filter(|line| !matches!(line.action, DiffAction::Delete))
+                    .count();
+                ChunkDiff {
+                    from: line_point(lines[0].from_line_number, old),
+                    to: line_point(lines[0].to_line_number, new),
+                    parents: vec![],
+                    section_heading: self.section_heading.clone(),
+                    lines,
+                }
+            })
+            .collect()
+    }
+
+    fn lines_where(&self, keep: impl Fn(&DiffAction) -> bool) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter(|line| keep(&line.action))
+            .map(|line| line.line.as_str())
+            .collect()
+    }
+
+    // Writes the chunk, adding a "\ No newline at end of file" marker after the
+    // last line of each side that `no_newline` flags as (old, new).
+    fn write(&self, f: &mut fmt::Formatter<'_>, no_newline: (bool, bool)) -> fmt::Result {
+        if self.parents.is_empty() {
+            write!(f, "@@ -{} +{} @@", range(&self.from), range(&self.to))?;
+        } else {
+            let marker = "@".repeat(self.parents.len() + 1);
+            write!(f, "{}", marker)?;
+            for parent in &self.parents {
+                write!(f, " -{}", range(parent))?;
+            }
+            write!(f, " +{} {}", range(&self.to), marker)?;
+        }
+        match self.section_heading {
+            Some(ref heading) => writeln!(f, " {}", heading)?,
+            None => writeln!(f)?,
+        }
+
+        let last_old = self
+            .lines
+            .iter()
+            .rposition(|line| !matches!(line.action, DiffAction::Add));
+        let last_new = self
+            .lines
+            .iter()
+            .rposition(|line| !matches!(line.action, DiffAction::Delete));
+        let columns = self.parents.len().max(1);
+        for (idx, line) in self.lines.iter().enumerate() {
+            let actions = if line.parent_actions.is_empty() {
+                vec![&line.action; columns]
+            } else {
+                line.parent_actions.iter().collect()
+            };
+            for action in actions {
+                write!(f, "{}", action.as_str())?;
+            }
+            if line.trailing_cr && !line.line.ends_with('\r') {
+                writeln!(f, "{}\r", line.line)?;
+            } else {
+                writeln!(f, "{}", line.line)?;
+            }
+            if (no_newline.0 && last_old == Some(idx)) || (no_newline.1 && last_new == Some(idx)) {
+                writeln!(f, "\\ No newline at end of file")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// "{START}" or "{START},{COUNT}"; git leaves out a count of 1.
+fn range(point: &LinePoint) -> String {
+    if point.line_count == 1 {
+        point.line_start.to_string()
+    } else {
+        format!("{},{}", point.line_start, point.line_count)
+    }
+}
+
+impl fmt::Display for ChunkDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, (false, false))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ChunkMeta {
     pub from_line_number: usize,
     pub to_line_number: usize,
+    pub parents: usize,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FileDiffPoint {
     pub file: String,
     pub mode: Option<String>,
     pub blob: Option<String>,
     pub end_newline: bool,
+    // The side is missing: "/dev/null" in the "---"/"+++" line of an add or delete.
+    pub is_dev_null: bool,
+    // The commit a submodule points to on this side.
+    pub submodule_commit: Option<String>,
+    // The path a symlink (mode 120000) points to on this side.
+    pub symlink_target: Option<String>,
+    // The text after the path in a GNU-style "---"/"+++" line, usually a date.
+    pub timestamp: Option<String>,
 }
 
-#[derive(Debug)]
+impl FileDiffPoint {
+    /// The side's blob from the `index` line, or `None` if there is none, it
+    /// isn't hex, or it is all zeros (the missing side of an add or delete).
+    pub fn blob_id(&self) -> Option<BlobId> {
+        BlobId::parse(self.blob.as_deref()?)
+    }
+
+    /// The side's mode, or `None` if there is none or it is all zeros (the
+    /// missing side of an add or delete).
+    pub fn file_mode(&self) -> Option<FileMode> {
+        FileMode::parse(self.mode.as_deref()?)
+    }
+}
+
+/// A blob hash from an `index` line.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum BlobId {
+    /// Abbreviated, as `git diff` prints by default.
+    Short(String),
+    /// A full SHA-1 (40 digits) or SHA-256 (64 digits) hash, as with `--full-index`.
+    Full(String),
+}
+
+impl BlobId {
+    /// Validates `hex` as a blob hash. The all-zeros hash of a missing side
+    /// yields `None`.
+    pub fn parse(hex: &str) -> Option<BlobId> {
+        if hex.is_empty()
+            || !hex.bytes().all(|b| b.is_ascii_hexdigit())
+            || hex.bytes().all(|b| b == b'0')
+        {
+            return None;
+        }
+        let hex = hex.to_ascii_lowercase();
+        Some(match hex.len() {
+            40 | 64 => BlobId::Full(hex),
+            _ => BlobId::Short(hex),
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            BlobId::Short(hex) | BlobId::Full(hex) => hex,
+        }
+    }
+
+    /// The hash function the ID comes from, if its length tells: only SHA-256
+    /// IDs are longer than 40 digits, and abbreviations shorter than that fit both.
+    pub fn object_format(&self) -> Option<ObjectFormat> {
+        match self.as_str().len() {
+            40 if matches!(self, BlobId::Full(_)) => Some(ObjectFormat::Sha1),
+            len if len > 40 => Some(ObjectFormat::Sha256),
+            _ => None,
+        }
+    }
+}
+
+/// A repository's object format, set by `git init --object-format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectFormat {
+    Sha1,
+    Sha256,
+}
+
+/// A file mode from a mode header or `index` line, such as `100644`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FileMode(pub u32);
+
+impl FileMode {
+    // The object type bits, as in `S_IFMT`.
+    const TYPE: u32 = 0o170000;
+    const REGULAR: u32 = 0o100000;
+    const SYMLINK: u32 = 0o120000;
+    const TREE: u32 = 0o040000;
+    const GITLINK: u32 = 0o160000;
+
+    /// Parses an octal mode. The all-zeros mode of a missing side yields `None`.
+    pub fn parse(octal: &str) -> Option<FileMode> {
+        match u32::from_str_radix(octal, 8) {
+            Ok(0) | Err(_) => None,
+            Ok(bits) => Some(FileMode(bits)),
+        }
+    }
+
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// A regular file, executable or not.
+    pub fn is_file(self) -> bool {
+        self.0 & Self::TYPE == Self::REGULAR
+    }
+
+    /// A regular file with any execute bit set, like `100755`.
+    pub fn is_executable(self) -> bool {
+        self.is_file() && self.0 & 0o111 != 0
+    }
+
+    pub fn is_symlink(self) -> bool {
+        self.0 & Self::TYPE == Self::SYMLINK
+    }
+
+    /// A directory, as in `040000`.
+    pub fn is_tree(self) -> bool {
+        self.0 & Self::TYPE == Self::TREE
+    }
+
+    /// A gitlink, the `160000` entry of a submodule.
+    pub fn is_submodule(self) -> bool {
+        self.0 & Self::TYPE == Self::GITLINK
+    }
+}
+
+impl fmt::Display for FileMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:06o}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FileDiff {
     pub from: FileDiffPoint,
     pub to: FileDiffPoint,
     pub is_binary: bool,
+    // Raw lines of a `GIT binary patch` block, still base85-encoded.
+    pub binary_patch: Option<Vec<String>>,
+    pub is_copy: bool,
+    pub is_submodule: bool,
+    pub is_symlink: bool,
+    // 1-based line of the input where the file's header is, or 0 when the
+    // diff wasn't parsed from text.
+    pub source_line: usize,
+    // The "similarity index" percentage of a rename or copy.
+    pub similarity: Option<u8>,
+    // The "dissimilarity index" percentage of a complete rewrite.
+    pub dissimilarity: Option<u8>,
     pub chunks: Vec<ChunkDiff>,
 }
 
-pub type ParsedLines = Vec<(String, HashMap<String, String>, String)>;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeType {
+    Added,
+    Deleted,
+    Modified,
+    Renamed,
+    Copied,
+    ModeChanged,
+}
 
-pub fn aggregator(lines: &ParsedLines) -> Vec<FileDiff> {
-    let mut file_diff: Option<FileDiff> = None;
-    let mut file_meta: Option<FileMeta> = None;
-    let mut chunk_diff: Option<ChunkDiff> = None;
-    let mut chunk_meta: Option<ChunkMeta> = None;
+impl FileDiff {
+    pub fn change_type(&self) -> ChangeType {
+        if self.is_copy {
+            ChangeType::Copied
+        } else if self.from.is_dev_null || self.from.mode.as_deref() == Some("0000000") {
+            ChangeType::Added
+        } else if self.to.is_dev_null || self.to.mode.as_deref() == Some("0000000") {
+            ChangeType::Deleted
+        } else if self.from.file != self.to.file {
+            ChangeType::Renamed
+        } else if self.from.mode != self.to.mode {
+            ChangeType::ModeChanged
+        } else {
+            ChangeType::Modified
+        }
+    }
 
-    let mut file_diffs = vec![];
+    /// How similar a renamed or copied file is to its source, in percent.
+    pub fn similarity(&self) -> Option<u8> {
+        self.similarity
+    }
 
-    for (state, parsed, _) in lines {
-        if state == "file_diff_header" {
-            if let Some(diff) = file_diff {
-                file_diffs.push(diff);
-
-                //file_diff = None;
-                //file_meta = None;
-                chunk_diff = None;
-                chunk_meta = None;
-            }
-
-            file_meta = Some(FileMeta {
-                no_newline_count: 0,
-            });
-            file_diff = Some(FileDiff {
-                from: FileDiffPoint {
-                    file: parsed.get("from_file").unwrap().to_string(),
-                    mode: None,
-                    blob: None,
-                    end_newline: true,
-                },
-                to: FileDiffPoint {
-                    file: parsed.get("to_file").unwrap().to_string(),
-                    mode: None,
-                    blob: None,
-                    end_newline: true,
-                },
-                is_binary: false,
-                chunks: vec![],
-            });
-            continue;
-        }
-
-        if state == "new_file_mode_header" {
-            let mode = parsed.get("mode").unwrap().to_string();
-            if let Some(ref mut file_diff) = file_diff {
-                file_diff.from.mode = Some("0000000".to_string());
-                file_diff.to.mode = Some(mode);
-            } else {
-                unreachable!();
-            }
-            continue;
+    /// How much of a rewritten file changed, in percent.
+    pub fn dissimilarity(&self) -> Option<u8> {
+        self.dissimilarity
+    }
+
+    /// The old path, or `None` when the file was added.
+    pub fn old_path(&self) -> Option<&str> {
+        if self.from.is_dev_null {
+            None
+        } else {
+            Some(&self.from.file)
         }
+    }
 
-        if state == "old_mode_header" {
-            let mode = parsed.get("mode").unwrap().to_string();
-            if let Some(ref mut file_diff) = file_diff {
-                file_diff.from.mode = Some(mode);
-            } else {
-                unreachable!();
-            }
-            continue;
+    /// The new path, or `None` when the file was deleted.
+    pub fn new_path(&self) -> Option<&str> {
+        if self.to.is_dev_null {
+            None
+        } else {
+            Some(&self.to.file)
+        }
+    }
+
+    /// The (source, destination) paths of a renamed or copied file, from its
+    /// `rename from`/`rename to` or `copy from`/`copy to` lines; `None` otherwise.
+    pub fn rename_info(&self) -> Option<(&str, &str)> {
+        match self.change_type() {
+            ChangeType::Renamed | ChangeType::Copied => Some((&self.from.file, &self.to.file)),
+            _ => None,
         }
+    }
 
-        if state == "new_mode_header" {
-            let mode = parsed.get("mode").unwrap().to_string();
-            if let Some(ref mut file_diff) = file_diff {
-                file_diff.to.mode = Some(mode);
+    /// Old side of every chunk, in order. Only the changed regions are covered, not
+    /// the whole file: use `from.line_start` of each chunk to splice them back in.
+    pub fn old_lines(&self) -> Vec<&str> {
+        self.chunks.iter().flat_map(ChunkDiff::old_lines).collect()
+    }
+
+    /// New side of every chunk, in order. Like [`FileDiff::old_lines`], this only
+    /// covers the changed regions; `to.line_start` tells where each one goes.
+    pub fn new_lines(&self) -> Vec<&str> {
+        self.chunks.iter().flat_map(ChunkDiff::new_lines).collect()
+    }
+
+    /// Every line of every chunk, paired with its chunk, e.g. to print every
+    /// added line:
+    ///
+    /// ```
+    /// use gitdiffparser::aggregator::DiffAction;
+    ///
+    /// let input = "diff --git a/f b/f\nindex 1111111..2222222 100644\n--- a/f\n+++ b/f\n\
+    ///              @@ -1 +1,2 @@\n a\n+b\n";
+    /// for file in gitdiffparser::parse(input).unwrap() {
+    ///     for (_, line) in file.lines() {
+    ///         if matches!(line.action, DiffAction::Add) {
+    ///             println!("{}:{}: {}", file.to.file, line.to_line_number, line.line);
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub fn lines(&self) -> impl Iterator<Item = (&ChunkDiff, &ChunkDiffLine)> {
+        self.chunks
+            .iter()
+            .flat_map(|chunk| chunk.lines.iter().map(move |line| (chunk, line)))
+    }
+
+    /// Whether the file has no content changes, like a pure rename or a mode
+    /// change: no hunks and not binary.
+    pub fn is_empty_change(&self) -> bool {
+        self.chunks.is_empty() && !self.is_binary
+    }
+
+    /// Number of hunks (`@@` sections) in the file.
+    pub fn hunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// Net lines added (negative if removed) by the hunks before hunk
+    /// `hunk_index`, i.e. how far that hunk's old lines have moved once the
+    /// earlier hunks are applied.
+    pub fn cumulative_offset_before(&self, hunk_index: usize) -> isize {
+        self.chunks
+            .iter()
+            .take(hunk_index)
+            .map(|chunk| chunk.to.line_count as isize - chunk.from.line_count as isize)
+            .sum()
+    }
+
+    /// Number of lines in the longest hunk, context included; 0 without hunks.
+    pub fn largest_hunk_lines(&self) -> usize {
+        self.chunks
+            .iter()
+            .map(|chunk| chunk.lines.len())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The new-file lines from the first hunk's start to the last hunk's end, the
+    /// end being one past the last line. `None` for binary files and files
+    /// without hunks.
+    pub fn new_line_span(&self) -> Option<(usize, usize)> {
+        if self.is_binary {
+            return None;
+        }
+        let (first, last) = (self.chunks.first()?, self.chunks.last()?);
+        Some((first.to.line_start, last.to.line_start + last.to.line_count))
+    }
+
+    /// The chunk whose new-side range covers line `n` of the new file.
+    pub fn hunk_for_new_line(&self, n: usize) -> Option<&ChunkDiff> {
+        self.chunks.iter().find(|chunk| chunk.contains_new_line(n))
+    }
+
+    /// Checks that every chunk holds as many old and new lines as its `@@` header
+    /// declares, which catches truncated or hand-edited diffs.
+    pub fn validate(&self) -> Result<(), AggregateError> {
+        for (idx, chunk) in self.chunks.iter().enumerate() {
+            let found = (chunk.old_lines().len(), chunk.new_lines().len());
+            let expected = (chunk.from.line_count, chunk.to.line_count);
+            // The first parent's count can't be recovered from a combined diff's
+            // collapsed actions, so only the new side is checked there.
+            let mismatch = if chunk.parents.is_empty() {
+                found != expected
             } else {
-                unreachable!();
+                found.1 != expected.1
+            };
+            if mismatch {
+                return Err(AggregateError::ChunkLineCountMismatch {
+                    chunk: idx,
+                    expected,
+                    found,
+                });
             }
-            continue;
         }
+        Ok(())
+    }
 
-        if state == "deleted_file_mode_header" {
-            let mode = parsed.get("mode").unwrap().to_string();
-            if let Some(ref mut file_diff) = file_diff {
-                file_diff.from.mode = Some(mode);
-                file_diff.to.mode = Some("0000000".to_string());
-            } else {
-                unreachable!();
+    /// Checks that chunks come in order and don't overlap, in both the old and the
+    /// new file, as an apply step expects.
+    pub fn validate_hunk_ordering(&self) -> Result<(), AggregateError> {
+        for (idx, pair) in self.chunks.windows(2).enumerate() {
+            let (prev, next) = (&pair[0], &pair[1]);
+            let in_order = |prev: &LinePoint, next: &LinePoint| {
+                first_line_number(next) >= first_line_number(prev) + prev.line_count
+            };
+            if !in_order(&prev.from, &next.from) || !in_order(&prev.to, &next.to) {
+                return Err(AggregateError::OverlappingChunks(idx + 1));
             }
-            continue;
         }
+        Ok(())
+    }
 
-        let states = ["a_file_change_header", "b_file_change_header"];
-        if states.contains(&state.as_str()) {
-            if let Some(ref mut file_diff) = file_diff {
-                let file = match state.as_str() {
-                    "a_file_change_header" => &file_diff.from.file,
-                    "b_file_change_header" => &file_diff.to.file,
-                    _ => panic!("unknown state"),
-                };
+    /// Trims the context of each hunk to at most `n` lines around its changes,
+    /// like `git diff -U<n>`, splitting hunks where changes are more than `2n`
+    /// context lines apart. Every piece keeps the original section heading, as
+    /// the lines git would pick a new one from aren't in the diff.
+    pub fn with_context_limit(mut self, n: usize) -> FileDiff {
+        // Trimming the file's last line drops any "\ No newline" marker with it.
+        let trims_end = self
+            .chunks
+            .last()
+            .is_some_and(|chunk| chunk.parents.is_empty() && chunk.trailing_context() > n);
+        self.chunks = self
+            .chunks
+            .into_iter()
+            .flat_map(|chunk| chunk.limit_context(n))
+            .collect();
+        if trims_end {
+            self.from.end_newline = true;
+            self.to.end_newline = true;
+        }
+        self
+    }
 
-                let f = parsed.get("file");
-                if Some(file) != f && f != None {
-                    println!("{:?} {:?}", file_diff, parsed);
-                    panic!("TODO: Exception text");
+    /// The diff that undoes this one, like `git diff -R`: the old and new sides
+    /// are swapped, and so are additions and deletions.
+    pub fn reversed(mut self) -> FileDiff {
+        core::mem::swap(&mut self.from, &mut self.to);
+        for chunk in &mut self.chunks {
+            core::mem::swap(&mut chunk.from, &mut chunk.to);
+            for line in &mut chunk.lines {
+                line.action = line.action.inverse();
+                for action in &mut line.parent_actions {
+                    *action = action.inverse();
+                }
+            }
+            // Git lists a change's deletions before its additions.
+            let mut start = 0;
+            while start < chunk.lines.len() {
+                let len = chunk.lines[start..]
+                    .iter()
+                    .take_while(|line| !matches!(line.action, DiffAction::Context))
+                    .count();
+                chunk.lines[start..start + len]
+                    .sort_by_key(|line| matches!(line.action, DiffAction::Add));
+                start += len.max(1);
+            }
+            let mut from_line_number = first_line_number(&chunk.from);
+            let mut to_line_number = first_line_number(&chunk.to);
+            for line in &mut chunk.lines {
+                line.from_line_number = from_line_number;
+                line.to_line_number = to_line_number;
+                if !matches!(line.action, DiffAction::Add) {
+                    from_line_number += 1;
+                }
+                if !matches!(line.action, DiffAction::Delete) {
+                    to_line_number += 1;
                 }
             }
-
-            continue;
         }
+        self
+    }
 
-        if state == "binary_diff" {
-            if let Some(ref mut file_diff) = file_diff {
-                file_diff.is_binary = true;
+    /// Joins hunks that pick up exactly where the previous one ends, in both the
+    /// old and the new file. Hunks with any lines between them stay apart: the
+    /// diff doesn't hold those lines, so the gap can't be filled with context,
+    /// which is why this takes no gap size.
+    pub fn merge_adjacent_hunks(mut self) -> FileDiff {
+        let mut chunks: Vec<ChunkDiff> = vec![];
+        for chunk in self.chunks {
+            let follows = |prev: &LinePoint, next: &LinePoint| {
+                first_line_number(next) == first_line_number(prev) + prev.line_count
+            };
+            match chunks.last_mut() {
+                Some(prev)
+                    if prev.parents.is_empty()
+                        && chunk.parents.is_empty()
+                        && follows(&prev.from, &chunk.from)
+                        && follows(&prev.to, &chunk.to) =>
+                {
+                    prev.from = line_point(
+                        first_line_number(&prev.from),
+                        prev.from.line_count + chunk.from.line_count,
+                    );
+                    prev.to = line_point(
+                        first_line_number(&prev.to),
+                        prev.to.line_count + chunk.to.line_count,
+                    );
+                    prev.lines.extend(chunk.lines);
+                }
+                _ => chunks.push(chunk),
             }
-            continue;
         }
+        self.chunks = chunks;
+        self
+    }
 
-        if state == "index_diff_header" {
-            if let Some(ref mut file_diff) = file_diff {
-                let from_blob = parsed.get("from_blob").unwrap().to_string();
-                file_diff.from.blob = Some(from_blob);
-                let to_blob = parsed.get("to_blob").unwrap().to_string();
-                file_diff.to.blob = Some(to_blob);
-            } else {
-                unreachable!();
-            }
+    /// (additions, deletions), like `git diff --numstat`. Binary files are (0, 0).
+    pub fn stats(&self) -> (usize, usize) {
+        if self.is_binary {
+            return (0, 0);
+        }
+        self.chunks
+            .iter()
+            .flat_map(|chunk| chunk.lines.iter())
+            .fold((0, 0), |(additions, deletions), line| match line.action {
+                DiffAction::Add => (additions + 1, deletions),
+                DiffAction::Delete => (additions, deletions + 1),
+                DiffAction::Context => (additions, deletions),
+            })
+    }
+}
 
-            // todo: finish this
-            let mode = parsed.get("mode");
-            if mode != None {
-                if let Some(ref mut file_diff) = file_diff {
-                    file_diff.from.mode = Some(mode.unwrap().to_string());
-                    file_diff.to.mode = Some(mode.unwrap().to_string());
-                }
-            }
+// Writes the file back as git would, normalised: paths always get the `a/` and
+// `b/` prefixes, whatever prefixes (or none) the input used, so the output
+// re-parses with the default config. Header lines end in `\n`; content lines
+// keep a `\r` only when parsed with `preserve_cr`. Re-parsing gives an equal
+// FileDiff apart from `source_line`.
+impl fmt::Display for FileDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let change_type = self.change_type();
+        let combined = self.chunks.iter().any(|chunk| !chunk.parents.is_empty());
+        let from = quote_path(&format!("a/{}", self.from.file));
+        let to = quote_path(&format!("b/{}", self.to.file));
+
+        // A plain unified diff (no blobs or modes) starts right at "---".
+        let plain = [&self.from, &self.to]
+            .iter()
+            .all(|side| side.blob.is_none() && side.mode.is_none())
+            && !self.chunks.is_empty();
 
-            continue;
+        if combined {
+            writeln!(f, "diff --cc {}", quote_path(&self.to.file))?;
+        } else if !plain {
+            writeln!(f, "diff --git {} {}", from, to)?;
         }
 
-        if state == "chunk_header" {
-            let from_line_start = parsed.get("from_line_start").unwrap();
-            let to_line_start = parsed.get("to_line_start").unwrap();
+        // Plain unified diffs have no modes, even for adds and deletes.
+        match (change_type, &self.from.mode, &self.to.mode) {
+            (ChangeType::Added, _, Some(mode)) => writeln!(f, "new file mode {}", mode)?,
+            (ChangeType::Deleted, Some(mode), _) => writeln!(f, "deleted file mode {}", mode)?,
+            (ChangeType::Added | ChangeType::Deleted, _, _) => {}
+            (_, Some(old), Some(new)) if old != new => {
+                writeln!(f, "old mode {}", old)?;
+                writeln!(f, "new mode {}", new)?;
+            }
+            _ => {}
+        }
 
-            chunk_meta = Some(ChunkMeta {
-                from_line_number: from_line_start.parse().unwrap(),
-                to_line_number: to_line_start.parse().unwrap(),
-            });
+        if let Some(dissimilarity) = self.dissimilarity {
+            writeln!(f, "dissimilarity index {}%", dissimilarity)?;
+        }
 
-            let from_line_count = parsed.get("from_line_count").unwrap();
-            let to_line_count = parsed.get("to_line_count").unwrap();
-            let diff = ChunkDiff {
-                from: LinePoint {
-                    line_start: from_line_start.parse().unwrap(),
-                    line_count: from_line_count.parse().unwrap(),
-                },
-                to: LinePoint {
-                    line_start: to_line_start.parse().unwrap(),
-                    line_count: to_line_count.parse().unwrap(),
-                },
-                lines: vec![],
+        if let Some(similarity) = self.similarity {
+            let kind = match change_type {
+                ChangeType::Copied => "copy",
+                _ => "rename",
             };
-            chunk_diff = Some(diff.clone());
-            if let Some(ref mut file_diff) = file_diff {
-                file_diff.chunks.push(diff);
+            writeln!(f, "similarity index {}%", similarity)?;
+            writeln!(f, "{} from {}", kind, quote_path(&self.from.file))?;
+            writeln!(f, "{} to {}", kind, quote_path(&self.to.file))?;
+        }
+
+        if let (Some(from_blob), Some(to_blob)) = (&self.from.blob, &self.to.blob) {
+            write!(f, "index {}..{}", from_blob, to_blob)?;
+            match (&self.from.mode, &self.to.mode) {
+                (Some(old), Some(new)) if old == new => writeln!(f, " {}", new)?,
+                _ => writeln!(f)?,
+            }
+        }
+
+        let (from, to) = match change_type {
+            ChangeType::Added => ("/dev/null".to_string(), to),
+            ChangeType::Deleted => (from, "/dev/null".to_string()),
+            _ => (from, to),
+        };
+
+        if let Some(ref patch) = self.binary_patch {
+            writeln!(f, "GIT binary patch")?;
+            for line in patch {
+                writeln!(f, "{}", line)?;
+            }
+        } else if self.is_binary {
+            writeln!(f, "Binary files {} and {} differ", from, to)?;
+        } else if !self.chunks.is_empty() {
+            for (marker, path, side) in [("---", from, &self.from), ("+++", to, &self.to)] {
+                match &side.timestamp {
+                    Some(timestamp) => writeln!(f, "{} {}\t{}", marker, path, timestamp)?,
+                    None => writeln!(f, "{} {}", marker, path)?,
+                }
+            }
+        }
+
+        let no_newline = (!self.from.end_newline, !self.to.end_newline);
+        for (idx, chunk) in self.chunks.iter().enumerate() {
+            if idx + 1 == self.chunks.len() {
+                chunk.write(f, no_newline)?;
             } else {
-                unreachable!();
+                chunk.write(f, (false, false))?;
             }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum AggregateError {
+    MissingFileHeader,
+    LineDiffOutsideChunk,
+    TooManyNoNewlineMarkers,
+    // A "---"/"+++" path that disagrees with the "diff --git" header.
+    FileNameMismatch {
+        expected: String,
+        found: String,
+    },
+    // The chunk at this index starts before the end of the one preceding it.
+    OverlappingChunks(usize),
+    // (old, new) line counts of the chunk at index `chunk`.
+    ChunkLineCountMismatch {
+        chunk: usize,
+        expected: (usize, usize),
+        found: (usize, usize),
+    },
+}
 
-            continue;
+impl fmt::Display for AggregateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AggregateError::MissingFileHeader => write!(f, "line found before any file header"),
+            AggregateError::LineDiffOutsideChunk => write!(f, "line_diff found outside a chunk"),
+            AggregateError::TooManyNoNewlineMarkers => {
+                write!(f, "more than one no_newline marker for the same side")
+            }
+            AggregateError::FileNameMismatch { expected, found } => write!(
+                f,
+                "file_change_header path {:?} doesn't match {:?} from the file diff header",
+                found, expected
+            ),
+            AggregateError::OverlappingChunks(chunk) => {
+                write!(
+                    f,
+                    "chunk {} overlaps or precedes the chunk before it",
+                    chunk
+                )
+            }
+            AggregateError::ChunkLineCountMismatch {
+                chunk,
+                expected,
+                found,
+            } => write!(
+                f,
+                "chunk {} has {} old and {} new lines, but its header declares {} and {}",
+                chunk, found.0, found.1, expected.0, expected.1
+            ),
         }
+    }
+}
 
-        if state == "line_diff" {
-            if let Some(ref chunk_meta) = chunk_meta {
-                let from_line_number = chunk_meta.from_line_number;
-                let to_line_number = chunk_meta.to_line_number;
-                let a = parsed.get("action").unwrap();
-                let action = DiffAction::from_str(a).unwrap();
+impl core::error::Error for AggregateError {}
 
-                let chunk_diff_line = ChunkDiffLine {
-                    from_line_number,
-                    to_line_number,
-                    line: parsed.get("line").unwrap().to_string(),
-                    action,
+pub type ParsedLines = Vec<ParsedLine>;
+
+// An empty range such as "-5,0" names the line *before* the chunk, so its
+// first line number is the one after.
+pub(crate) fn first_line_number(point: &LinePoint) -> usize {
+    if point.line_count == 0 {
+        point.line_start + 1
+    } else {
+        point.line_start
+    }
+}
+
+// The range of `count` lines starting at line `start`. Like git, an empty range
+// starts at the line before it.
+pub(crate) fn line_point(start: usize, count: usize) -> LinePoint {
+    LinePoint {
+        line_start: if count == 0 {
+            start.saturating_sub(1)
+        } else {
+            start
+        },
+        line_count: count,
+    }
+}
+
+// The text git prints after the closing "@@", usually the enclosing function.
+fn section_heading(section: &str) -> Option<String> {
+    let heading = section.trim();
+    if heading.is_empty() {
+        None
+    } else {
+        Some(heading.to_string())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Aggregator {
+    file_diff: Option<FileDiff>,
+    file_meta: Option<FileMeta>,
+    chunk_meta: Option<ChunkMeta>,
+}
+
+impl Aggregator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn current_file(&mut self) -> Result<&mut FileDiff, AggregateError> {
+        self.file_diff
+            .as_mut()
+            .ok_or(AggregateError::MissingFileHeader)
+    }
+
+    /// Feeds one parsed line, returning the previous file once the next one starts.
+    pub fn push(&mut self, state: &LineKind) -> Result<Option<FileDiff>, AggregateError> {
+        self.push_at(state, 0)
+    }
+
+    /// Like [`push`](Self::push), for line `line_number` (1-based) of the input,
+    /// which becomes the `source_line` of a file it starts.
+    pub fn push_at(
+        &mut self,
+        state: &LineKind,
+        line_number: usize,
+    ) -> Result<Option<FileDiff>, AggregateError> {
+        match state {
+            LineKind::FileDiffHeader(_)
+            | LineKind::CombinedFileDiffHeader(_)
+            | LineKind::PlainAFileChangeHeader(_) => {
+                let (from_file, to_file) = match state {
+                    LineKind::FileDiffHeader(header) => {
+                        (header.from_file.as_str(), header.to_file.as_str())
+                    }
+                    LineKind::CombinedFileDiffHeader(file) => (file.as_str(), file.as_str()),
+                    // Filled in by the "+++" line that follows.
+                    LineKind::PlainAFileChangeHeader(header) => {
+                        (header.file.as_deref().unwrap_or_default(), "")
+                    }
+                    _ => unreachable!(),
+                };
+
+                let finished = self.file_diff.take();
+                self.chunk_meta = None;
+                let (from_dev_null, from_timestamp) = match state {
+                    LineKind::PlainAFileChangeHeader(header) => {
+                        (header.file.is_none(), header.timestamp.clone())
+                    }
+                    _ => (false, None),
                 };
 
-                if let Some(ref mut chunk_diff) = chunk_diff {
-                    chunk_diff.lines.push(chunk_diff_line);
+                self.file_meta = Some(FileMeta {
+                    plain: matches!(state, LineKind::PlainAFileChangeHeader(_)),
+                    exact_paths: match state {
+                        LineKind::FileDiffHeader(header) => header.exact,
+                        _ => true,
+                    },
+                });
+                self.file_diff = Some(FileDiff {
+                    from: FileDiffPoint {
+                        file: from_file.to_string(),
+                        mode: None,
+                        blob: None,
+                        end_newline: true,
+                        is_dev_null: from_dev_null,
+                        submodule_commit: None,
+                        symlink_target: None,
+                        timestamp: from_timestamp,
+                    },
+                    to: FileDiffPoint {
+                        file: to_file.to_string(),
+                        mode: None,
+                        blob: None,
+                        end_newline: true,
+                        is_dev_null: false,
+                        submodule_commit: None,
+                        symlink_target: None,
+                        timestamp: None,
+                    },
+                    is_binary: false,
+                    binary_patch: None,
+                    is_copy: false,
+                    is_submodule: false,
+                    is_symlink: false,
+                    source_line: line_number,
+                    similarity: None,
+                    dissimilarity: None,
+                    chunks: vec![],
+                });
+                return Ok(finished);
+            }
+
+            LineKind::NewFileModeHeader(mode) => {
+                let file_diff = self.current_file()?;
+                file_diff.from.mode = Some("0000000".to_string());
+                file_diff.from.is_dev_null = true;
+                file_diff.to.mode = Some(mode.to_string());
+            }
+
+            LineKind::OldModeHeader(mode) => {
+                let file_diff = self.current_file()?;
+                file_diff.from.mode = Some(mode.to_string());
+            }
+
+            LineKind::NewModeHeader(mode) => {
+                let file_diff = self.current_file()?;
+                file_diff.to.mode = Some(mode.to_string());
+            }
+
+            LineKind::DeletedFileModeHeader(mode) => {
+                let file_diff = self.current_file()?;
+                file_diff.from.mode = Some(mode.to_string());
+                file_diff.to.mode = Some("0000000".to_string());
+                file_diff.to.is_dev_null = true;
+            }
+
+            LineKind::BFileChangeHeader(header)
+                if matches!(self.file_meta, Some(FileMeta { plain: true, .. })) =>
+            {
+                let file_diff = self.current_file()?;
+                file_diff.to.timestamp = header.timestamp.clone();
+                match &header.file {
+                    Some(f) => {
+                        if file_diff.from.file.is_empty() {
+                            file_diff.from.file = f.to_string();
+                        }
+                        file_diff.to.file = f.to_string();
+                    }
+                    None => {
+                        file_diff.to.file = file_diff.from.file.clone();
+                        file_diff.to.is_dev_null = true;
+                    }
+                }
+            }
+
+            LineKind::AFileChangeHeader(header) | LineKind::BFileChangeHeader(header) => {
+                let exact = self
+                    .file_meta
+                    .as_ref()
+                    .is_some_and(|file_meta| file_meta.exact_paths);
+                let file_diff = self.current_file()?;
+                // An unquoted "diff --git" line with spaces in its paths can be split
+                // in the wrong place, so then the header only has to contain the path.
+                let paths = format!("{} {}", file_diff.from.file, file_diff.to.file);
+                let is_a = matches!(state, LineKind::AFileChangeHeader(_));
+                let side = if is_a {
+                    &mut file_diff.from
                 } else {
-                    unreachable!();
+                    &mut file_diff.to
+                };
+                side.timestamp = header.timestamp.clone();
+                match &header.file {
+                    None => side.is_dev_null = true,
+                    Some(f) => {
+                        let consistent = if exact {
+                            *f == side.file
+                        } else if is_a {
+                            paths.starts_with(f.as_str())
+                        } else {
+                            paths.ends_with(f.as_str())
+                        };
+                        if !consistent {
+                            return Err(AggregateError::FileNameMismatch {
+                                expected: side.file.to_string(),
+                                found: f.to_string(),
+                            });
+                        }
+                        side.file = f.to_string();
+                    }
                 }
-            } else {
-                unreachable!();
             }
 
-            let action = parsed.get("action").unwrap();
-            if [" ", "-"].contains(&action.as_str()) {
-                if let Some(ref mut chunk_meta) = chunk_meta {
-                    chunk_meta.from_line_number += 1;
+            LineKind::BinaryDiff => {
+                self.current_file()?.is_binary = true;
+            }
+
+            LineKind::GitBinaryPatch => {
+                let file_diff = self.current_file()?;
+                file_diff.is_binary = true;
+                file_diff.binary_patch = Some(vec![]);
+            }
+
+            LineKind::BinaryPatchLine(line) => {
+                if let Some(ref mut patch) = self.current_file()?.binary_patch {
+                    patch.push(line.to_string());
                 }
             }
-            if [" ", "+"].contains(&action.as_str()) {
-                if let Some(ref mut chunk_meta) = chunk_meta {
-                    chunk_meta.to_line_number += 1;
+
+            LineKind::IndexDiffHeader(index) => {
+                let file_diff = self.current_file()?;
+                file_diff.from.blob = Some(index.from_blob.to_string());
+                file_diff.to.blob = Some(index.to_blob.to_string());
+
+                // The trailing mode only applies when no mode header has set one already.
+                if let Some(ref mode) = index.mode {
+                    file_diff.from.mode.get_or_insert_with(|| mode.to_string());
+                    file_diff.to.mode.get_or_insert_with(|| mode.to_string());
                 }
             }
 
-            if let Some(ref file_meta) = file_meta {
-                if file_meta.no_newline_count > 0 {
-                    if let Some(ref mut file_diff) = file_diff {
-                        file_diff.to.end_newline = true;
-                        file_diff.from.end_newline = true;
+            LineKind::ChunkHeader(header) => {
+                self.chunk_meta = Some(ChunkMeta {
+                    from_line_number: first_line_number(&header.from),
+                    to_line_number: first_line_number(&header.to),
+                    parents: 0,
+                });
+
+                let diff = ChunkDiff {
+                    from: header.from.clone(),
+                    to: header.to.clone(),
+                    parents: vec![],
+                    section_heading: section_heading(&header.section),
+                    lines: vec![],
+                };
+                let file_diff = self.current_file()?;
+                file_diff.chunks.push(diff);
+            }
+
+            LineKind::CombinedChunkHeader(header) => {
+                self.chunk_meta = Some(ChunkMeta {
+                    from_line_number: first_line_number(&header.parents[0]),
+                    to_line_number: first_line_number(&header.to),
+                    parents: header.parents.len(),
+                });
+
+                let diff = ChunkDiff {
+                    from: header.parents[0].clone(),
+                    to: header.to.clone(),
+                    parents: header.parents.clone(),
+                    section_heading: section_heading(&header.section),
+                    lines: vec![],
+                };
+                let file_diff = self.current_file()?;
+                file_diff.chunks.push(diff);
+            }
+
+            LineKind::LineDiff(line_diff) => {
+                let mut action = line_diff.action.clone();
+                let mut line = line_diff.line.as_str();
+                let mut in_from = !matches!(action, DiffAction::Add);
+                let mut in_to = !matches!(action, DiffAction::Delete);
+                let mut parent_actions = vec![];
+
+                // Combined diffs prefix each line with one action column per parent. The
+                // first column was parsed as `action`, the others still lead `line`.
+                let parents = self.chunk_meta.as_ref().map_or(0, |meta| meta.parents);
+                if parents > 1 {
+                    let split = line
+                        .char_indices()
+                        .nth(parents - 1)
+                        .map_or(line.len(), |(i, _)| i);
+                    let (columns, content) = line.split_at(split);
+                    parent_actions.push(action.clone());
+                    parent_actions.extend(columns.chars().map(|column| match column {
+                        '-' => DiffAction::Delete,
+                        '+' => DiffAction::Add,
+                        _ => DiffAction::Context,
+                    }));
+                    let deleted = matches!(action, DiffAction::Delete) || columns.contains('-');
+                    let added = matches!(action, DiffAction::Add) || columns.contains('+');
+
+                    in_from = matches!(action, DiffAction::Delete)
+                        || (matches!(action, DiffAction::Context) && !deleted);
+                    in_to = !deleted;
+                    action = if deleted {
+                        DiffAction::Delete
+                    } else if added {
+                        DiffAction::Add
+                    } else {
+                        DiffAction::Context
+                    };
+                    line = content;
+                }
+
+                let chunk_meta = self
+                    .chunk_meta
+                    .as_mut()
+                    .ok_or(AggregateError::LineDiffOutsideChunk)?;
+                let chunk_diff = self
+                    .file_diff
+                    .as_mut()
+                    .and_then(|f| f.chunks.last_mut())
+                    .ok_or(AggregateError::LineDiffOutsideChunk)?;
+
+                chunk_diff.lines.push(ChunkDiffLine {
+                    from_line_number: chunk_meta.from_line_number,
+                    to_line_number: chunk_meta.to_line_number,
+                    line: line.to_string(),
+                    action,
+                    parent_actions,
+                    trailing_cr: line_diff.trailing_cr,
+                });
+
+                if in_from {
+                    chunk_meta.from_line_number += 1;
+                }
+                if in_to {
+                    chunk_meta.to_line_number += 1;
+                }
+
+                // A gitlink (mode 160000) has "Subproject commit {SHA}" as its content.
+                let file_diff = self.current_file()?;
+                let is_gitlink = [&file_diff.from, &file_diff.to]
+                    .iter()
+                    .any(|side| side.file_mode().is_some_and(FileMode::is_submodule));
+                if let Some(commit) = line.strip_prefix("Subproject commit ") {
+                    if is_gitlink {
+                        file_diff.is_submodule = true;
+                        if in_from {
+                            file_diff.from.submodule_commit = Some(commit.to_string());
+                        }
+                        if in_to {
+                            file_diff.to.submodule_commit = Some(commit.to_string());
+                        }
                     }
                 }
+
+                // A symlink's content is its target, on a single line.
+                let is_symlink =
+                    |side: &FileDiffPoint| side.file_mode().is_some_and(FileMode::is_symlink);
+                if in_from && is_symlink(&file_diff.from) {
+                    file_diff.is_symlink = true;
+                    file_diff.from.symlink_target = Some(line.to_string());
+                }
+                if in_to && is_symlink(&file_diff.to) {
+                    file_diff.is_symlink = true;
+                    file_diff.to.symlink_target = Some(line.to_string());
+                }
             }
 
-            continue;
-        }
+            LineKind::NoNewline => {
+                // The marker belongs to the side(s) of the line right before it, and
+                // each side can end without a newline only once.
+                let file_diff = self.current_file()?;
+                let action = file_diff
+                    .chunks
+                    .last()
+                    .and_then(|chunk| chunk.lines.last())
+                    .map(|line| line.action.clone())
+                    .ok_or(AggregateError::LineDiffOutsideChunk)?;
+                let (old_side, new_side) = match action {
+                    DiffAction::Delete => (true, false),
+                    DiffAction::Add => (false, true),
+                    DiffAction::Context => (true, true),
+                };
+                if (old_side && !file_diff.from.end_newline)
+                    || (new_side && !file_diff.to.end_newline)
+                {
+                    return Err(AggregateError::TooManyNoNewlineMarkers);
+                }
+                if old_side {
+                    file_diff.from.end_newline = false;
+                }
+                if new_side {
+                    file_diff.to.end_newline = false;
+                }
+            }
 
-        if state == "no_newline" {
-            if let Some(ref mut file_meta) = file_meta {
-                file_meta.no_newline_count += 1;
-                if file_meta.no_newline_count > 2 {
-                    panic!("TODO: Exception text");
+            // Unlike the "diff --git" line, these name each path unambiguously.
+            LineKind::RenameAFile(file) | LineKind::CopyAFile(file) => {
+                let file_diff = self.current_file()?;
+                file_diff.is_copy = matches!(state, LineKind::CopyAFile(_));
+                file_diff.from.file = file.to_string();
+            }
+
+            LineKind::RenameBFile(file) | LineKind::CopyBFile(file) => {
+                if let Some(file_meta) = self.file_meta.as_mut() {
+                    file_meta.exact_paths = true;
                 }
-            } else {
-                unreachable!();
+                let file_diff = self.current_file()?;
+                file_diff.is_copy = matches!(state, LineKind::CopyBFile(_));
+                file_diff.to.file = file.to_string();
             }
-            if let Some(ref mut file_diff) = file_diff {
-                file_diff.to.end_newline = false;
-            } else {
-                unreachable!();
+
+            LineKind::RenameHeader(rate) => {
+                self.current_file()?.similarity = rate.parse().ok();
+            }
+
+            LineKind::DissimilarityHeader(rate) => {
+                self.current_file()?.dissimilarity = rate.parse().ok();
             }
-            continue;
+
+            LineKind::StartOfFile => {}
         }
 
-        println!("file_diffs: {:?}", file_diffs);
-        unreachable!("unexpected {:?} line", state);
+        Ok(None)
     }
 
-    if let Some(file_diff) = file_diff {
+    /// Returns the file still being built, if any.
+    pub fn finish(&mut self) -> Option<FileDiff> {
+        self.chunk_meta = None;
+        self.file_meta = None;
+        self.file_diff.take()
+    }
+}
+
+pub fn aggregator(lines: &ParsedLines) -> Result<Vec<FileDiff>, AggregateError> {
+    let mut aggregator = Aggregator::new();
+    let mut file_diffs = vec![];
+
+    for line in lines {
+        if let Some(file_diff) = aggregator.push_at(&line.kind, line.line_number)? {
+            file_diffs.push(file_diff);
+        }
+    }
+
+    if let Some(file_diff) = aggregator.finish() {
         file_diffs.push(file_diff);
     }
 
-    file_diffs
+    Ok(file_diffs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::line_parser::{ParseError, ParserConfig};
+
+    #[test]
+    fn chunk_header_without_counts() {
+        let input = "diff --git a/f b/f\nindex 1111111..2222222 100644\n--- a/f\n+++ b/f\n\
+                     @@ -5 +5 @@\n-old\n+new\n";
+        let diffs: Vec<FileDiff> = crate::parse(input).unwrap();
+        let chunk = &diffs[0].chunks[0];
+        assert_eq!((chunk.from.line_start, chunk.from.line_count), (5, 1));
+        assert_eq!((chunk.to.line_start, chunk.to.line_count), (5, 1));
+        let numbers: Vec<_> = chunk
+            .lines
+            .iter()
+            .map(|line| {
+                (
+                    line.action.as_str(),
+                    line.from_line_number,
+                    line.to_line_number,
+                )
+            })
+            .collect();
+        assert_eq!(numbers, [("-", 5, 5), ("+", 6, 5)]);
+    }
+
+    #[test]
+    fn input_without_final_newline() {
+        let input = "diff --git a/f b/f\nindex 1111111..2222222 100644\n--- a/f\n+++ b/f\n\
+                     @@ -1,2 +1,2 @@\n a\n-b\n+c";
+        let diffs = crate::parse(input).unwrap();
+        assert_eq!(diffs.len(), 1);
+        let lines = &diffs[0].chunks[0].lines;
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[2].line, "c");
+        assert!(diffs[0].to.end_newline);
+    }
+
+    #[test]
+    fn file_change_headers_must_match_unambiguous_paths() {
+        let input = "diff --git a/xyz b/xyz\nindex 1111111..2222222 100644\n--- a/x\n+++ b/z\n@@ -1 +1 @@\n-a\n+b\n";
+        match crate::parse(input) {
+            Err(ParseError::AggregateError(_, AggregateError::FileNameMismatch { .. })) => {}
+            other => panic!("unexpected result: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn file_change_headers_resolve_ambiguous_paths() {
+        let input = "diff --git a/a b/c b/a b/c\nindex 1111111..2222222 100644\n--- a/a b/c\n+++ b/a b/c\n@@ -1 +1 @@\n-a\n+b\n";
+        let diffs = crate::parse(input).unwrap();
+        assert_eq!(diffs[0].from.file, "a b/c");
+        assert_eq!(diffs[0].to.file, "a b/c");
+    }
+
+    #[test]
+    fn aggregator_source_lines_count_skipped_lines() {
+        let file = |name: &str| {
+            format!(
+                "diff --git a/{name} b/{name}\nindex 1111111..2222222 100644\n\
+                 --- a/{name}\n+++ b/{name}\n@@ -1 +1 @@\n-a\n+b\n"
+            )
+        };
+        let input = format!("\n\n{}{}{}", file("x"), file("y"), file("z"));
+        let lines = crate::line_parser::parse_lines(input.lines()).unwrap();
+        let source_lines: Vec<_> = aggregator(&lines)
+            .unwrap()
+            .iter()
+            .map(|diff| diff.source_line)
+            .collect();
+        let parsed: Vec<_> = crate::parse(&input)
+            .unwrap()
+            .iter()
+            .map(|diff| diff.source_line)
+            .collect();
+        assert_eq!(source_lines, [3, 10, 17]);
+        assert_eq!(source_lines, parsed);
+    }
+
+    #[test]
+    fn keeps_dissimilarity_index() {
+        let input = "diff --git a/f b/f\ndissimilarity index 100%\n\
+                     index 1111111..2222222 100644\n--- a/f\n+++ b/f\n@@ -1 +1 @@\n-a\n+b\n";
+        let diffs = crate::parse(input).unwrap();
+        assert_eq!(diffs[0].dissimilarity(), Some(100));
+        assert_eq!(diffs[0].to_string(), input);
+    }
+
+    #[test]
+    fn pure_mode_change() {
+        let input = "diff --git a/f b/f\nold mode 100644\nnew mode 100755\n\
+                     diff --git a/g b/g\nold mode 100755\nnew mode 100644\n";
+        let diffs = crate::parse(input).unwrap();
+        assert_eq!(diffs.len(), 2);
+        for diff in &diffs {
+            assert_eq!(diff.change_type(), ChangeType::ModeChanged);
+            assert!(diff.chunks.is_empty());
+        }
+        assert_eq!(diffs[0].from.mode.as_deref(), Some("100644"));
+        assert_eq!(diffs[0].to.mode.as_deref(), Some("100755"));
+    }
+
+    #[test]
+    fn no_newline_after_context_line() {
+        let input = "diff --git a/f b/f\nindex 1111111..2222222 100644\n--- a/f\n+++ b/f\n\
+                     @@ -1,2 +1,2 @@\n-a\n+b\n c\n\\ No newline at end of file\n";
+        let diffs = crate::parse(input).unwrap();
+        assert!(!diffs[0].from.end_newline);
+        assert!(!diffs[0].to.end_newline);
+    }
+
+    #[test]
+    fn large_function_context_hunk() {
+        let body = 5000;
+        let mut input = format!(
+            "diff --git a/f.c b/f.c\nindex 1111111..2222222 100644\n--- a/f.c\n+++ b/f.c\n\
+             @@ -10,{} +10,{} @@ int main(void)\n {{\n",
+            body + 2,
+            body + 3,
+        );
+        for n in 0..body {
+            input.push_str(&format!(" x += {n};\n"));
+        }
+        input.push_str("+return 0;\n }\n");
+        let diffs = crate::parse(&input).unwrap();
+        let chunk = &diffs[0].chunks[0];
+        assert_eq!(chunk.section_heading.as_deref(), Some("int main(void)"));
+        assert_eq!(chunk.lines.len(), body + 3);
+        let last = chunk.lines.last().unwrap();
+        assert_eq!(
+            (last.from_line_number, last.to_line_number),
+            (body + 11, body + 12)
+        );
+        assert_eq!(diffs[0].stats(), (1, 0));
+    }
+
+    #[test]
+    fn consecutive_pure_renames() {
+        let input = "diff --git a/a b/b\nsimilarity index 100%\nrename from a\nrename to b\n\
+                     diff --git a/c b/d\nsimilarity index 100%\nrename from c\nrename to d\n";
+        let diffs = crate::parse(input).unwrap();
+        let paths: Vec<_> = diffs
+            .iter()
+            .map(|diff| (diff.from.file.as_str(), diff.to.file.as_str()))
+            .collect();
+        assert_eq!(paths, [("a", "b"), ("c", "d")]);
+        for diff in &diffs {
+            assert_eq!(diff.change_type(), ChangeType::Renamed);
+            assert!(diff.chunks.is_empty());
+            assert!(!diff.is_binary);
+        }
+    }
+
+    #[test]
+    fn index_line_without_content() {
+        let input = "diff --git a/f.bin b/f.bin\nindex 1111111..2222222 100644\n\
+                     diff --git a/g.bin b/g.bin\nindex 3333333..4444444 100644\n";
+        let diffs = crate::parse(input).unwrap();
+        assert_eq!(diffs.len(), 2);
+        assert_eq!(diffs[1].from.blob.as_deref(), Some("3333333"));
+        assert!(diffs.iter().all(|diff| diff.chunks.is_empty()));
+    }
+
+    #[test]
+    fn no_newline_markers_on_both_sides() {
+        let input = "diff --git a/f b/f\nindex 1111111..2222222 100644\n--- a/f\n+++ b/f\n\
+                     @@ -1 +1 @@\n-old\n\\ No newline at end of file\n+new\n\
+                     \\ No newline at end of file\n";
+        let diffs = crate::parse(input).unwrap();
+        assert!(!diffs[0].from.end_newline);
+        assert!(!diffs[0].to.end_newline);
+        assert_eq!(diffs[0].chunks[0].lines.len(), 2);
+        assert_eq!(diffs[0].to_string(), input);
+    }
+
+    #[test]
+    fn index_mode_does_not_clobber_mode_headers() {
+        let input = "diff --git a/f b/f\nold mode 100644\nnew mode 100755\n\
+                     index 1111111..2222222 100644\n--- a/f\n+++ b/f\n@@ -1 +1 @@\n-a\n+b\n";
+        let diffs = crate::parse(input).unwrap();
+        assert_eq!(diffs[0].from.mode.as_deref(), Some("100644"));
+        assert_eq!(diffs[0].to.mode.as_deref(), Some("100755"));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn equal_file_diffs_hash_equal() {
+        use core::hash::{Hash, Hasher};
+        use std::collections::hash_map::DefaultHasher;
+
+        let hash = |diff: &FileDiff| {
+            let mut hasher = DefaultHasher::new();
+            diff.hash(&mut hasher);
+            hasher.finish()
+        };
+        let input = "diff --git a/f b/f\nindex 1111111..2222222 100644\n--- a/f\n+++ b/f\n\
+                     @@ -1 +1 @@\n-a\n+b\n";
+        let (a, b) = (crate::parse(input).unwrap(), crate::parse(input).unwrap());
+        assert_eq!(a, b);
+        assert_eq!(hash(&a[0]), hash(&b[0]));
+    }
+
+    #[test]
+    fn diff_action_char_round_trips() {
+        for action in [DiffAction::Add, DiffAction::Delete, DiffAction::Context] {
+            let parsed: DiffAction = action.as_char().to_string().parse().unwrap();
+            assert_eq!(parsed, action);
+            assert_eq!(action.to_string(), action.as_char().to_string());
+        }
+    }
+
+    #[test]
+    fn merges_only_contiguous_hunks() {
+        let input = "diff --git a/f b/f\nindex 1111111..2222222 100644\n--- a/f\n+++ b/f\n\
+                     @@ -1,2 +1,2 @@\n a\n-b\n+B\n@@ -3,2 +3,2 @@\n c\n-d\n+D\n\
+                     @@ -7 +7 @@\n-g\n+G\n";
+        let diff = crate::parse(input)
+            .unwrap()
+            .remove(0)
+            .merge_adjacent_hunks();
+        assert_eq!(diff.chunks.len(), 2);
+        let point = LinePoint {
+            line_start: 1,
+            line_count: 4,
+        };
+        assert_eq!(diff.chunks[0].from, point);
+        assert_eq!(diff.chunks[0].to, point);
+        assert_eq!(diff.chunks[0].lines.len(), 6);
+        assert_eq!(diff.chunks[1].from.line_start, 7);
+    }
+
+    #[test]
+    fn extra_no_newline_markers() {
+        let third = "diff --git a/f b/f\nindex 1111111..2222222 100644\n--- a/f\n+++ b/f\n\
+                     @@ -1 +1 @@\n-old\n\\ No newline at end of file\n+new\n\
+                     \\ No newline at end of file\n\\ No newline at end of file\n";
+        assert!(matches!(
+            crate::parse(third),
+            Err(crate::line_parser::ParseError::Unexpected { line: 10, .. })
+        ));
+
+        let same_side = "diff --git a/f b/f\nindex 1111111..2222222 100644\n--- a/f\n+++ b/f\n\
+                         @@ -1,2 +0,0 @@\n-a\n\\ No newline at end of file\n-b\n\
+                         \\ No newline at end of file\n";
+        assert!(matches!(
+            crate::parse(same_side),
+            Err(crate::line_parser::ParseError::AggregateError(
+                9,
+                AggregateError::TooManyNoNewlineMarkers
+            ))
+        ));
+    }
+
+    #[test]
+    fn two_file_plain_unified_diff() {
+        let input = "--- a/one.txt\n+++ b/one.txt\n@@ -1,2 +1,2 @@\n keep\n-old\n+new\n\
+                     --- a/two.txt\n+++ b/two.txt\n@@ -1 +1,2 @@\n same\n+added\n";
+        let diffs = crate::parse(input).unwrap();
+        assert_eq!(diffs.len(), 2);
+        for (diff, file) in diffs.iter().zip(["one.txt", "two.txt"]) {
+            assert_eq!(diff.from.file, file);
+            assert_eq!(diff.to.file, file);
+            assert_eq!(diff.from.mode, None);
+            assert_eq!(diff.to.blob, None);
+            assert_eq!(diff.change_type(), ChangeType::Modified);
+        }
+        assert_eq!(diffs[0].stats(), (1, 1));
+        assert_eq!(diffs[1].stats(), (1, 0));
+        assert_eq!(diffs[1].source_line, 7);
+    }
+
+    #[test]
+    fn crlf_headers_and_content() {
+        let input = "diff --git a/f.txt b/f.txt\r\nindex 1111111..2222222 100644\r\n\
+                     --- a/f.txt\r\n+++ b/f.txt\r\n@@ -1,2 +1,2 @@ fn main()\r\n \
+                     keep\r\n-old\r\n+new\r\n";
+        let lines = |config| -> Vec<(String, bool)> {
+            let diffs = crate::parse_with_config(input, config).unwrap();
+            assert_eq!(diffs[0].from.file, "f.txt");
+            assert_eq!(diffs[0].to.file, "f.txt");
+            assert_eq!(diffs[0].to.mode.as_deref(), Some("100644"));
+            assert_eq!(
+                diffs[0].chunks[0].section_heading.as_deref(),
+                Some("fn main()")
+            );
+            diffs[0].chunks[0]
+                .lines
+                .iter()
+                .map(|line| (line.line.clone(), line.trailing_cr))
+                .collect()
+        };
+
+        let dropped = lines(ParserConfig::default());
+        assert_eq!(
+            dropped,
+            [
+                ("keep".to_string(), true),
+                ("old".to_string(), true),
+                ("new".to_string(), true)
+            ]
+        );
+
+        let preserved = lines(ParserConfig {
+            preserve_cr: true,
+            ..ParserConfig::default()
+        });
+        assert_eq!(
+            preserved,
+            [
+                ("keep\r".to_string(), true),
+                ("old\r".to_string(), true),
+                ("new\r".to_string(), true)
+            ]
+        );
+    }
+
+    #[test]
+    fn dev_null_versus_a_dev_null_path() {
+        let input = "diff --git a/dev/null b/dev/null\nindex 1111111..2222222 100644\n\
+                     --- a/dev/null\n+++ b/dev/null\n@@ -1 +1 @@\n-a\n+b\n\
+                     diff --git a/new.txt b/new.txt\nnew file mode 100644\n\
+                     index 0000000..3333333\n--- /dev/null\n+++ b/new.txt\n@@ -0,0 +1 @@\n+c\n";
+        let diffs = crate::parse(input).unwrap();
+        assert_eq!(diffs[0].from.file, "dev/null");
+        assert_eq!(diffs[0].to.file, "dev/null");
+        assert!(!diffs[0].from.is_dev_null && !diffs[0].to.is_dev_null);
+        assert_eq!(diffs[0].change_type(), ChangeType::Modified);
+        assert!(diffs[1].from.is_dev_null);
+        assert!(!diffs[1].to.is_dev_null);
+        assert_eq!(diffs[1].change_type(), ChangeType::Added);
+    }
+
+    #[test]
+    fn binary_patch_blocks_then_next_file() {
+        let input = "diff --git a/new.bin b/new.bin\nnew file mode 100644\n\
+                     index 0000000..bdc955b\nGIT binary patch\nliteral 2\nJcmZQz1ONa700IC2\n\n\
+                     literal 0\nHcmV?d00001\n\n\
+                     diff --git a/old.bin b/old.bin\nindex 1111111..2222222 100644\n\
+                     GIT binary patch\ndelta 14\nVcmZp0XmFUIAT-gqfq~&a6$2v_0{{X|1N8s^\n\n\
+                     delta 14\nVcmZp0XmFUIAT%|Aqk$<h0{{X|1MdI;\n\n\
+                     diff --git a/f b/f\nindex 3333333..4444444 100644\n--- a/f\n+++ b/f\n\
+                     @@ -1 +1 @@\n-a\n+b\n";
+        let diffs = crate::parse(input).unwrap();
+        assert_eq!(diffs.len(), 3);
+        assert!(diffs[0].is_binary && diffs[1].is_binary && !diffs[2].is_binary);
+        assert_eq!(
+            diffs[0].binary_patch.as_deref(),
+            Some(
+                &[
+                    "literal 2",
+                    "JcmZQz1ONa700IC2",
+                    "",
+                    "literal 0",
+                    "HcmV?d00001",
+                    ""
+                ]
+                .map(String::from)[..]
+            )
+        );
+        assert_eq!(
+            diffs[1].binary_patch.as_deref(),
+            Some(
+                &[
+                    "delta 14",
+                    "VcmZp0XmFUIAT-gqfq~&a6$2v_0{{X|1N8s^",
+                    "",
+                    "delta 14",
+                    "VcmZp0XmFUIAT%|Aqk$<h0{{X|1MdI;",
+                    "",
+                ]
+                .map(String::from)[..]
+            )
+        );
+        assert_eq!(diffs[2].to.file, "f");
+        assert_eq!(diffs[2].stats(), (1, 1));
+    }
+
+    #[test]
+    fn header_timestamps() {
+        let input = "diff --git a/my file.txt b/my file.txt\nindex 1111111..2222222 100644\n\
+                     --- a/my file.txt\t\n+++ b/my file.txt\t\n@@ -1 +1 @@\n-a\n+b\n\
+                     --- p1\t2026-10-15 09:28:05.797763286 +0000\n\
+                     +++ p2\t2026-10-15 09:28:06.000000000 +0000\n@@ -1 +1 @@\n-c\n+d\n";
+        let diffs = crate::parse(input).unwrap();
+
+        // The tab git adds after a path with spaces isn't part of it, and an
+        // empty timestamp is none.
+        assert_eq!(diffs[0].from.file, "my file.txt");
+        assert_eq!(diffs[0].to.file, "my file.txt");
+        assert_eq!(diffs[0].from.timestamp, None);
+        assert_eq!(diffs[0].to.timestamp, None);
+
+        assert_eq!(diffs[1].from.file, "p1");
+        assert_eq!(diffs[1].to.file, "p2");
+        assert_eq!(
+            diffs[1].from.timestamp.as_deref(),
+            Some("2026-10-15 09:28:05.797763286 +0000")
+        );
+        assert_eq!(
+            diffs[1].to.timestamp.as_deref(),
+            Some("2026-10-15 09:28:06.000000000 +0000")
+        );
+    }
+
+    #[test]
+    fn file_mode_kinds() {
+        let kinds = |octal| {
+            let mode = FileMode::parse(octal).unwrap();
+            (
+                mode.is_file(),
+                mode.is_executable(),
+                mode.is_symlink(),
+                mode.is_submodule(),
+                mode.is_tree(),
+            )
+        };
+        assert_eq!(kinds("100644"), (true, false, false, false, false));
+        assert_eq!(kinds("100755"), (true, true, false, false, false));
+        assert_eq!(kinds("120000"), (false, false, true, false, false));
+        assert_eq!(kinds("160000"), (false, false, false, true, false));
+        assert_eq!(kinds("040000"), (false, false, false, false, true));
+
+        assert_eq!(FileMode::parse("100755").unwrap().bits(), 0o100755);
+        assert_eq!(FileMode::parse("040000").unwrap().to_string(), "040000");
+        assert_eq!(FileMode::parse("000000"), None);
+        assert_eq!(FileMode::parse("100855"), None);
+        assert_eq!(FileMode::parse(""), None);
+    }
+
+    #[test]
+    fn blob_id_lengths_and_case() {
+        assert_eq!(
+            BlobId::parse("ABC1234"),
+            Some(BlobId::Short("abc1234".to_string()))
+        );
+        let full = "0123456789ABCDEF0123456789abcdef01234567";
+        assert_eq!(
+            BlobId::parse(full),
+            Some(BlobId::Full(full.to_ascii_lowercase()))
+        );
+        assert_eq!(BlobId::parse(full).unwrap().as_str().len(), 40);
+        assert_eq!(BlobId::parse("0000000"), None);
+        assert_eq!(BlobId::parse("abc12xz"), None);
+        assert_eq!(BlobId::parse(""), None);
+    }
+
+    #[test]
+    fn sha256_index_line() {
+        let (old, new) = (
+            "1f3a6a8bd5e2d23f1bdb62e7a6b9d6c3b7b29a4a8de3a4d02f18b5a3d56cbe11",
+            "9e4c0b7a3b6b7d1c5f3e2a9d8c7b6a5f4e3d2c1b0a9f8e7d6c5b4a3f2e1d0c9b",
+        );
+        let input = format!(
+            "diff --git a/f b/f\nindex {old}..{new} 100644\n--- a/f\n+++ b/f\n\
+             @@ -1 +1 @@\n-a\n+b\n"
+        );
+        let diffs = crate::parse(&input).unwrap();
+        let from = diffs[0].from.blob_id().unwrap();
+        assert_eq!(from, BlobId::Full(old.to_string()));
+        assert_eq!(from.object_format(), Some(ObjectFormat::Sha256));
+        assert_eq!(diffs[0].to.blob_id().unwrap().as_str(), new);
+        assert_eq!(diffs[0].to.mode.as_deref(), Some("100644"));
+
+        let sha1 = BlobId::parse("0123456789abcdef0123456789abcdef01234567").unwrap();
+        assert_eq!(sha1.object_format(), Some(ObjectFormat::Sha1));
+        assert_eq!(BlobId::parse("abc1234").unwrap().object_format(), None);
+    }
+
+    #[test]
+    fn pure_addition_and_deletion_hunks() {
+        let input = "diff --git a/f b/f\nindex 1111111..2222222 100644\n--- a/f\n+++ b/f\n\
+                     @@ -1,2 +1,3 @@\n a\n+b\n c\n@@ -10,2 +11,1 @@\n d\n-e\n\
+                     @@ -20 +20 @@\n-f\n+g\n@@ -30 +30 @@\n h\n";
+        let diffs = crate::parse(input).unwrap();
+        let kinds: Vec<_> = diffs[0]
+            .chunks
+            .iter()
+            .map(|chunk| (chunk.is_pure_addition(), chunk.is_pure_deletion()))
+            .collect();
+        assert_eq!(
+            kinds,
+            [(true, false), (false, true), (false, false), (false, false)]
+        );
+    }
+
+    #[test]
+    fn rename_with_mode_change() {
+        let input = "diff --git a/run.sh b/start.sh\nold mode 100644\nnew mode 100755\n\
+                     similarity index 75%\nrename from run.sh\nrename to start.sh\n\
+                     index a7e1e16..75bb6c6\n--- a/run.sh\n+++ b/start.sh\n@@ -2,4 +2,4 @@\n \
+                     echo 1\n echo 2\n echo 3\n-echo 4\n+echo four\n";
+        let diffs = crate::parse(input).unwrap();
+        let diff = &diffs[0];
+        assert_eq!(diff.change_type(), ChangeType::Renamed);
+        assert_eq!(diff.rename_info(), Some(("run.sh", "start.sh")));
+        assert_eq!(diff.similarity(), Some(75));
+        assert_eq!(diff.from.mode.as_deref(), Some("100644"));
+        assert_eq!(diff.to.mode.as_deref(), Some("100755"));
+        assert_eq!(diff.to.blob.as_deref(), Some("75bb6c6"));
+        assert_eq!(diff.stats(), (1, 1));
+        assert_eq!(diff.to_string(), input);
+    }
+
+    // Four hunks: a mixed one, a deletion, a pure addition with an empty old
+    // side and a pure deletion with an empty new side.
+    const HUNKS: &str = "diff --git a/f b/f\nindex 1111111..2222222 100644\n--- a/f\n+++ b/f\n\
+                         @@ -2,3 +2,4 @@ fn one()\n a\n+b\n c\n d\n@@ -10,3 +11,2 @@\n e\n-f\n g\n\
+                         @@ -20,0 +21,2 @@\n+h\n+i\n@@ -30 +31,0 @@\n-j\n";
+
+    #[test]
+    fn cumulative_offsets() {
+        let diff = &crate::parse(HUNKS).unwrap()[0];
+        let offsets: Vec<_> = (0..=5)
+            .map(|idx| diff.cumulative_offset_before(idx))
+            .collect();
+        // Past the last hunk, every hunk counts.
+        assert_eq!(offsets, [0, 1, 0, 2, 1, 1]);
+    }
+
+    #[test]
+    fn empty_changes() {
+        let input = "diff --git a/run.sh b/run.sh\nold mode 100644\nnew mode 100755\n\
+                     diff --git a/empty b/empty\nnew file mode 100644\nindex 0000000..e69de29\n\
+                     diff --git a/f b/f\nindex 1111111..2222222 100644\n--- a/f\n+++ b/f\n\
+                     @@ -1 +1 @@\n-a\n+b\n\
+                     diff --git a/img.png b/img.png\nindex 3333333..4444444 100644\n\
+                     Binary files a/img.png and b/img.png differ\n";
+        let diffs = crate::parse(input).unwrap();
+        assert_eq!(diffs[0].change_type(), ChangeType::ModeChanged);
+        assert_eq!(diffs[1].change_type(), ChangeType::Added);
+        let empty: Vec<_> = diffs.iter().map(FileDiff::is_empty_change).collect();
+        assert_eq!(empty, [true, true, false, false]);
+    }
+
+    #[test]
+    fn hunk_line_ranges() {
+        let diff = &crate::parse(HUNKS).unwrap()[0];
+        let (mixed, added, deleted) = (&diff.chunks[0], &diff.chunks[2], &diff.chunks[3]);
+
+        let old: Vec<_> = (1..=5).map(|n| mixed.contains_old_line(n)).collect();
+        assert_eq!(old, [false, true, true, true, false]);
+        let new: Vec<_> = (1..=6).map(|n| mixed.contains_new_line(n)).collect();
+        assert_eq!(new, [false, true, true, true, true, false]);
+
+        // A zero-count side contains no line, not even the one it names.
+        assert!(!added.contains_old_line(20) && !added.contains_old_line(21));
+        assert!(added.contains_new_line(21) && added.contains_new_line(22));
+        assert!(deleted.contains_old_line(30));
+        assert!(!deleted.contains_new_line(31) && !deleted.contains_new_line(30));
+    }
+
+    #[test]
+    fn hunk_for_new_lines() {
+        let diff = &crate::parse(HUNKS).unwrap()[0];
+        let hunk = |n| {
+            diff.hunk_for_new_line(n).map(|chunk| {
+                diff.chunks
+                    .iter()
+                    .position(|c| core::ptr::eq(c, chunk))
+                    .unwrap()
+            })
+        };
+        assert_eq!(hunk(2), Some(0));
+        assert_eq!(hunk(5), Some(0));
+        assert_eq!(hunk(12), Some(1));
+        assert_eq!(hunk(22), Some(2));
+        // Between hunks, on an empty new side and past the last hunk.
+        assert_eq!(hunk(1), None);
+        assert_eq!(hunk(8), None);
+        assert_eq!(hunk(31), None);
+        assert_eq!(hunk(100), None);
+    }
+
+    #[test]
+    fn leading_and_trailing_context() {
+        let diff = &crate::parse(HUNKS).unwrap()[0];
+        let context: Vec<_> = diff
+            .chunks
+            .iter()
+            .map(|chunk| (chunk.leading_context(), chunk.trailing_context()))
+            .collect();
+        assert_eq!(context, [(1, 2), (1, 1), (0, 0), (0, 0)]);
+    }
+
+    #[test]
+    fn largest_hunk() {
+        let diff = &crate::parse(HUNKS).unwrap()[0];
+        assert_eq!(diff.largest_hunk_lines(), 4);
+
+        let rename = "diff --git a/a b/b\nsimilarity index 100%\nrename from a\nrename to b\n";
+        assert_eq!(crate::parse(rename).unwrap()[0].largest_hunk_lines(), 0);
+    }
+
+    #[test]
+    fn new_line_spans() {
+        let diff = &crate::parse(HUNKS).unwrap()[0];
+        // From the first hunk's start to one past the last: its new side is
+        // empty, so that is where it starts.
+        assert_eq!(diff.new_line_span(), Some((2, 31)));
+
+        let input = "diff --git a/f b/f\nindex 1111111..2222222 100644\n--- a/f\n+++ b/f\n\
+                     @@ -5,2 +5,3 @@\n a\n+b\n c\n\
+                     diff --git a/a b/b\nsimilarity index 100%\nrename from a\nrename to b\n\
+                     diff --git a/img.png b/img.png\nindex 3333333..4444444 100644\n\
+                     Binary files a/img.png and b/img.png differ\n";
+        let spans: Vec<_> = crate::parse(input)
+            .unwrap()
+            .iter()
+            .map(FileDiff::new_line_span)
+            .collect();
+        assert_eq!(spans, [Some((5, 8)), None, None]);
+    }
+
+    #[test]
+    fn context_limit_splits_hunks() {
+        let input = "diff --git a/f b/f\nindex 1111111..2222222 100644\n--- a/f\n+++ b/f\n\
+                     @@ -1,10 +1,11 @@ fn main()\n a\n-b\n+B\n c\n d\n e\n f\n g\n+new\n h\n i\n j\n";
+        let diff = crate::parse(input).unwrap().remove(0);
+        let ranges = |diff: &FileDiff| -> Vec<_> {
+            diff.chunks
+                .iter()
+                .map(|chunk| {
+                    (
+                        (chunk.from.line_start, chunk.from.line_count),
+                        (chunk.to.line_start, chunk.to.line_count),
+                    )
+                })
+                .collect()
+        };
+
+        // Five context lines apart is more than twice the limit: two hunks.
+        let limited = diff.clone().with_context_limit(1);
+        assert_eq!(ranges(&limited), [((1, 3), (1, 3)), ((7, 2), (7, 3))]);
+        assert_eq!(limited.chunks[1].old_lines(), ["g", "h"]);
+        assert_eq!(limited.chunks[1].new_lines(), ["g", "new", "h"]);
+        assert_eq!(limited.chunks[1].lines[1].to_line_number, 8);
+        assert_eq!(
+            limited.chunks[1].section_heading.as_deref(),
+            Some("fn main()")
+        );
+        limited.validate().unwrap();
+
+        // With no context, the addition's old side is empty and names the line before.
+        let bare = diff.clone().with_context_limit(0);
+        assert_eq!(ranges(&bare), [((2, 1), (2, 1)), ((7, 0), (8, 1))]);
+
+        // Within the limit, nothing changes.
+        assert_eq!(diff.clone().with_context_limit(3), diff);
+    }
+
+    #[test]
+    fn rename_info_of_binary_and_hunkless_files() {
+        let input = "diff --git a/img.png b/img.png\nindex 3333333..4444444 100644\n\
+                     Binary files a/img.png and b/img.png differ\n\
+                     diff --git a/run.sh b/run.sh\nold mode 100644\nnew mode 100755\n\
+                     diff --git a/a b/b\nsimilarity index 100%\nrename from a\nrename to b\n\
+                     diff --git a/old.png b/new.png\nsimilarity index 90%\nrename from old.png\n\
+                     rename to new.png\nindex 5555555..6666666 100644\n\
+                     Binary files a/old.png and b/new.png differ\n";
+        let infos: Vec<_> = crate::parse(input)
+            .unwrap()
+            .iter()
+            .map(|diff| {
+                diff.rename_info()
+                    .map(|(from, to)| (from.to_string(), to.to_string()))
+            })
+            .collect();
+        assert_eq!(
+            infos,
+            [
+                None,
+                None,
+                Some(("a".to_string(), "b".to_string())),
+                Some(("old.png".to_string(), "new.png".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn pure_add_and_delete_line_numbers() {
+        let input = "diff --git a/n b/n\nnew file mode 100644\nindex 0000000..1111111\n\
+                     --- /dev/null\n+++ b/n\n@@ -0,0 +1,2 @@\n+a\n+b\n\
+                     diff --git a/d b/d\ndeleted file mode 100644\nindex 1111111..0000000\n\
+                     --- a/d\n+++ /dev/null\n@@ -1,2 +0,0 @@\n-a\n-b\n";
+        let diffs = crate::parse(input).unwrap();
+        let numbers = |diff: &FileDiff| -> Vec<_> {
+            diff.chunks[0]
+                .lines
+                .iter()
+                .map(|line| (line.from_line_number, line.to_line_number))
+                .collect()
+        };
+
+        let added = &diffs[0].chunks[0];
+        assert_eq!((added.from.line_start, added.from.line_count), (0, 0));
+        assert_eq!((added.to.line_start, added.to.line_count), (1, 2));
+        // The empty side numbers every line as the next one there, line 1.
+        assert_eq!(numbers(&diffs[0]), [(1, 1), (1, 2)]);
+
+        let deleted = &diffs[1].chunks[0];
+        assert_eq!((deleted.from.line_start, deleted.from.line_count), (1, 2));
+        assert_eq!((deleted.to.line_start, deleted.to.line_count), (0, 0));
+        assert_eq!(numbers(&diffs[1]), [(1, 1), (2, 1)]);
+    }
+
+    #[test]
+    fn no_newline_marker_belongs_to_the_line_before() {
+        let after_delete = "diff --git a/f b/f\nindex 1111111..2222222 100644\n--- a/f\n+++ b/f\n\
+                            @@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n+c\n";
+        let diff = &crate::parse(after_delete).unwrap()[0];
+        assert!(!diff.from.end_newline);
+        assert!(diff.to.end_newline);
+
+        let after_add = "diff --git a/f b/f\nindex 1111111..2222222 100644\n--- a/f\n+++ b/f\n\
+                         @@ -1,2 +1,2 @@\n a\n-b\n+c\n\\ No newline at end of file\n";
+        let diff = &crate::parse(after_add).unwrap()[0];
+        assert!(diff.from.end_newline);
+        assert!(!diff.to.end_newline);
+    }
+}
+
+#[cfg(all(test, feature = "json"))]
+mod json_tests {
+    use super::*;
+
+    #[test]
+    fn json_round_trip() {
+        let input = "diff --git a/f b/f\nindex 1111111..2222222 100644\n--- a/f\n+++ b/f\n\
+                     @@ -1,2 +1,2 @@\n a\n-b\n+c\n";
+        let diffs = crate::parse(input).unwrap();
+        let json = serde_json::to_string(&diffs).unwrap();
+        assert!(json.contains(r#""action":"delete""#));
+        let parsed: Vec<FileDiff> = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, diffs);
+    }
 }
@@ -0,0 +1,254 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::aggregator::{first_line_number, ChunkDiff, ChunkDiffLine, DiffAction, FileDiff};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApplyError {
+    // Line `line` (1-based) of the source isn't the `expected` context or
+    // deleted line of the chunk at index `chunk`; `found` is `None` past the end.
+    Mismatch {
+        chunk: usize,
+        line: usize,
+        expected: String,
+        found: Option<String>,
+    },
+    // The chunk at this index starts before the end of the one preceding it.
+    OverlappingChunks(usize),
+    // The chunk at this index starts past the end of the source.
+    OutOfRange(usize),
+    // Binary files have no hunks to apply.
+    Binary,
+    // Combined diffs have more than one source.
+    Combined,
+}
+
+impl fmt::Display for ApplyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApplyError::Mismatch {
+                chunk,
+                line,
+                expected,
+                found: Some(found),
+            } => write!(
+                f,
+                "chunk {} expects {:?} at line {}, found {:?}",
+                chunk, expected, line, found
+            ),
+            ApplyError::Mismatch {
+                chunk,
+                line,
+                expected,
+                found: None,
+            } => write!(
+                f,
+                "chunk {} expects {:?} at line {}, past the end of the source",
+                chunk, expected, line
+            ),
+            ApplyError::OverlappingChunks(chunk) => {
+                write!(
+                    f,
+                    "chunk {} overlaps or precedes the chunk before it",
+                    chunk
+                )
+            }
+            ApplyError::OutOfRange(chunk) => {
+                write!(f, "chunk {} starts past the end of the source", chunk)
+            }
+            ApplyError::Binary => write!(f, "binary diffs can't be applied"),
+            ApplyError::Combined => write!(f, "combined diffs can't be applied"),
+        }
+    }
+}
+
+impl core::error::Error for ApplyError {}
+
+/// Applies `diff` to `source`, the old file's content, and returns the new
+/// content. Every context and deleted line must be at the line its chunk says.
+pub fn apply(source: &str, diff: &FileDiff) -> Result<String, ApplyError> {
+    apply_with_fuzz(source, diff, 0).map(|(out, _)| out)
+}
+
+/// Like [`apply`], but a chunk whose lines aren't where it says is looked for
+/// up to `fuzz` lines before or after, as `patch` does with a source that has
+/// changed a little. Also returns the offset each chunk was applied at.
+pub fn apply_with_fuzz(
+    source: &str,
+    diff: &FileDiff,
+    fuzz: usize,
+) -> Result<(String, Vec<isize>), ApplyError> {
+    if diff.is_binary {
+        return Err(ApplyError::Binary);
+    }
+    if diff.chunks.iter().any(|chunk| !chunk.parents.is_empty()) {
+        return Err(ApplyError::Combined);
+    }
+
+    // Lines keep a "\r" before their "\n", so CRLF sources are copied as they are.
+    let source_lines: Vec<&str> = crate::lines(source).collect();
+    let mut out = String::with_capacity(source.len());
+    let mut offsets = Vec::with_capacity(diff.chunks.len());
+    let mut cursor = 0;
+    for (idx, chunk) in diff.chunks.iter().enumerate() {
+        let declared = first_line_number(&chunk.from).saturating_sub(1) as isize;
+        // Like `patch`, an earlier chunk's offset carries over to the next one.
+        let expected = declared + offsets.last().copied().unwrap_or(0);
+        let fitting = (0..=fuzz as isize)
+            .flat_map(|shift| [expected - shift, expected + shift])
+            .find_map(|start| check(&source_lines, cursor, start, idx, chunk).ok());
+        let start = match fitting {
+            Some(start) => start,
+            None => check(&source_lines, cursor, expected, idx, chunk)?,
+        };
+        offsets.push(start as isize - declared);
+        for line in &source_lines[cursor..start] {
+            push_line(&mut out, line);
+        }
+
+        let mut pos = start;
+        for line in &chunk.lines {
+            match line.action {
+                DiffAction::Add => {
+                    push_line(&mut out, &line.line);
+                    if line.trailing_cr && !line.line.ends_with('\r') {
+                        out.insert(out.len() - 1, '\r');
+                    }
+                }
+                DiffAction::Context => {
+                    push_line(&mut out, source_lines[pos]);
+                    pos += 1;
+                }
+                DiffAction::Delete => pos += 1,
+            }
+        }
+        cursor = pos;
+    }
+    for line in &source_lines[cursor..] {
+        push_line(&mut out, line);
+    }
+
+    // The diff only tells whether the new file ends in a newline when its last
+    // chunk reaches the end; otherwise the source's ending is kept.
+    let end_newline = if cursor == source_lines.len() && !diff.chunks.is_empty() {
+        diff.to.end_newline
+    } else {
+        source.ends_with('\n')
+    };
+    if !end_newline && out.ends_with('\n') {
+        out.pop();
+    }
+    Ok((out, offsets))
+}
+
+/// Undoes `diff`, like `patch -R`: `source` is the new file's content, and the
+/// old content is returned.
+pub fn reverse_apply(source: &str, diff: &FileDiff) -> Result<String, ApplyError> {
+    apply(source, &diff.clone().reversed())
+}
+
+// Checks that the chunk at index `idx` can start at line index `start`, after
+// the source lines up to `cursor` are used, and returns `start`.
+fn check(
+    source_lines: &[&str],
+    cursor: usize,
+    start: isize,
+    idx: usize,
+    chunk: &ChunkDiff,
+) -> Result<usize, ApplyError> {
+    if start < cursor as isize {
+        return Err(ApplyError::OverlappingChunks(idx));
+    }
+    let start = start as usize;
+    if start > source_lines.len() {
+        return Err(ApplyError::OutOfRange(idx));
+    }
+    let old_lines = chunk
+        .lines
+        .iter()
+        .filter(|line| !matches!(line.action, DiffAction::Add));
+    for (pos, line) in (start..).zip(old_lines) {
+        let found = source_lines.get(pos).copied();
+        if !found.is_some_and(|found| is_same_line(found, line)) {
+            return Err(ApplyError::Mismatch {
+                chunk: idx,
+                line: pos + 1,
+                expected: line.line.clone(),
+                found: found.map(str::to_string),
+            });
+        }
+    }
+    Ok(start)
+}
+
+fn push_line(out: &mut String, line: &str) {
+    out.push_str(line);
+    out.push('\n');
+}
+
+// Whether a source line, which may end in "\r", is the chunk's line, whose "\r"
+// is only kept with `ParserConfig::preserve_cr`.
+fn is_same_line(source: &str, line: &ChunkDiffLine) -> bool {
+    source == line.line || (line.trailing_cr && source.strip_suffix('\r') == Some(&line.line))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIFF: &str = "diff --git a/f b/f\nindex 1111111..2222222 100644\n--- a/f\n+++ b/f\n\
+                        @@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n";
+
+    fn file_diff(input: &str) -> FileDiff {
+        crate::parse(input).unwrap().remove(0)
+    }
+
+    #[test]
+    fn applies_cleanly() {
+        assert_eq!(apply("a\nb\nc\n", &file_diff(DIFF)).unwrap(), "a\nB\nc\n");
+    }
+
+    #[test]
+    fn reports_a_context_mismatch() {
+        assert_eq!(
+            apply("a\nx\nc\n", &file_diff(DIFF)),
+            Err(ApplyError::Mismatch {
+                chunk: 0,
+                line: 2,
+                expected: "b".to_string(),
+                found: Some("x".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn applies_a_pure_addition_to_an_empty_file() {
+        let diff = file_diff(
+            "diff --git a/f b/f\nnew file mode 100644\nindex 0000000..2222222\n\
+             --- /dev/null\n+++ b/f\n@@ -0,0 +1,2 @@\n+a\n+b\n",
+        );
+        assert_eq!(apply("", &diff).unwrap(), "a\nb\n");
+    }
+
+    #[test]
+    fn fuzz_finds_a_shifted_hunk() {
+        let diff = file_diff(DIFF);
+        assert!(apply("\na\nb\nc\n", &diff).is_err());
+        let (applied, offsets) = apply_with_fuzz("\na\nb\nc\n", &diff, 1).unwrap();
+        assert_eq!(applied, "\na\nB\nc\n");
+        assert_eq!(offsets, [1]);
+    }
+
+    #[test]
+    fn reverse_apply_undoes_apply() {
+        let diff = file_diff(
+            "diff --git a/f b/f\nindex 1111111..2222222 100644\n--- a/f\n+++ b/f\n\
+             @@ -1,3 +1,4 @@\n a\n-b\n+B\n+b2\n c\n@@ -8,2 +9 @@\n h\n-i\n",
+        );
+        let source = "a\nb\nc\nd\ne\nf\ng\nh\ni\n";
+        let applied = apply(source, &diff).unwrap();
+        assert_eq!(applied, "a\nB\nb2\nc\nd\ne\nf\ng\nh\n");
+        assert_eq!(reverse_apply(&applied, &diff).unwrap(), source);
+    }
+}
@@ -0,0 +1,142 @@
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::aggregator::{line_point, AggregateError, Aggregator, DiffAction, FileDiff};
+use crate::line_parser::{ChunkHeaderData, FileHeaderData, LineDiffData, LineKind};
+
+// A chunk's first old and new line numbers and its lines.
+type ChunkLines = (usize, usize, Vec<(DiffAction, String)>);
+
+/// Builds a `FileDiff` in code. Chunk counts and line numbers are computed from
+/// the lines added, by feeding the same aggregator the parser uses.
+#[derive(Debug)]
+pub struct FileDiffBuilder {
+    from_file: String,
+    to_file: String,
+    chunks: Vec<ChunkLines>,
+    error: Option<AggregateError>,
+}
+
+impl FileDiffBuilder {
+    pub fn new(path: impl Into<String>) -> Self {
+        let path = path.into();
+        FileDiffBuilder {
+            from_file: path.clone(),
+            to_file: path,
+            chunks: vec![],
+            error: None,
+        }
+    }
+
+    pub fn rename(mut self, from: impl Into<String>, to: impl Into<String>) -> Self {
+        self.from_file = from.into();
+        self.to_file = to.into();
+        self
+    }
+
+    /// Starts a chunk whose first line is `from_start` in the old file and
+    /// `to_start` in the new one.
+    pub fn add_chunk(mut self, from_start: usize, to_start: usize) -> Self {
+        self.chunks.push((from_start, to_start, vec![]));
+        self
+    }
+
+    /// Appends a line to the last chunk started with `add_chunk`.
+    pub fn add_line(mut self, action: DiffAction, text: impl Into<String>) -> Self {
+        match self.chunks.last_mut() {
+            Some((_, _, lines)) => lines.push((action, text.into())),
+            None => {
+                self.error
+                    .get_or_insert(AggregateError::LineDiffOutsideChunk);
+            }
+        }
+        self
+    }
+
+    pub fn build(self) -> Result<FileDiff, AggregateError> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+
+        let mut aggregator = Aggregator::new();
+        aggregator.push(&LineKind::FileDiffHeader(FileHeaderData {
+            from_file: self.from_file,
+            to_file: self.to_file,
+            exact: true,
+        }))?;
+        for (from_start, to_start, lines) in self.chunks {
+            let old = lines
+                .iter()
+                .filter(|(action, _)| !matches!(action, DiffAction::Add))
+                .count();
+            let new = lines
+                .iter()
+                .filter(|(action, _)| !matches!(action, DiffAction::Delete))
+                .count();
+            aggregator.push(&LineKind::ChunkHeader(ChunkHeaderData {
+                from: line_point(from_start, old),
+                to: line_point(to_start, new),
+                section: String::new(),
+            }))?;
+            for (action, line) in lines {
+                aggregator.push(&LineKind::LineDiff(LineDiffData {
+                    action,
+                    line,
+                    remaining: None,
+                    trailing_cr: false,
+                }))?;
+            }
+        }
+        let file_diff = aggregator
+            .finish()
+            .ok_or(AggregateError::MissingFileHeader)?;
+        file_diff.validate_hunk_ordering()?;
+        Ok(file_diff)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+
+    use super::*;
+
+    #[test]
+    fn built_diff_round_trips_through_display() {
+        let built = FileDiffBuilder::new("src/main.rs")
+            .add_chunk(1, 1)
+            .add_line(DiffAction::Context, "fn main() {")
+            .add_line(DiffAction::Delete, "    old();")
+            .add_line(DiffAction::Add, "    new();")
+            .add_line(DiffAction::Add, "    more();")
+            .add_chunk(10, 11)
+            .add_line(DiffAction::Delete, "}")
+            .build()
+            .unwrap();
+        // Like git, an empty side names the line before it.
+        assert_eq!(built.chunks[1].to.line_start, 10);
+        assert_eq!(built.chunks[1].to.line_count, 0);
+
+        let output = built.to_string();
+        let parsed = crate::parse(&output).unwrap();
+        assert_eq!(
+            parsed,
+            [FileDiff {
+                source_line: 1,
+                ..built
+            }]
+        );
+    }
+
+    #[test]
+    fn out_of_order_chunks() {
+        let built = FileDiffBuilder::new("f")
+            .add_chunk(10, 10)
+            .add_line(DiffAction::Add, "b")
+            .add_chunk(1, 1)
+            .add_line(DiffAction::Add, "a")
+            .build();
+        assert!(matches!(built, Err(AggregateError::OverlappingChunks(1))));
+    }
+}
@@ -0,0 +1,164 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::aggregator::{ChunkDiff, DiffAction};
+
+/// A word in a chunk line: `line` indexes `ChunkDiff::lines`, and `start..end`
+/// are byte offsets into that line's text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InlineSpan {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Word-level differences between a run of deleted lines and the run of added
+/// lines right after it.
+#[derive(Debug, Clone)]
+pub struct InlineChange {
+    pub old_lines: Range<usize>,
+    pub new_lines: Range<usize>,
+    pub removed: Vec<InlineSpan>,
+    pub added: Vec<InlineSpan>,
+}
+
+// Whitespace-separated words of the given lines.
+fn words(chunk: &ChunkDiff, lines: Range<usize>) -> Vec<(&str, InlineSpan)> {
+    let mut words = vec![];
+    for idx in lines {
+        let text = chunk.lines[idx].line.as_str();
+        let mut start = None;
+        for (i, c) in text.char_indices().chain([(text.len(), ' ')]) {
+            match (start, c.is_whitespace()) {
+                (None, false) => start = Some(i),
+                (Some(s), true) => {
+                    words.push((
+                        &text[s..i],
+                        InlineSpan {
+                            line: idx,
+                            start: s,
+                            end: i,
+                        },
+                    ));
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+    }
+    words
+}
+
+// Splits both word lists into the words outside their longest common subsequence.
+fn unmatched(
+    old: Vec<(&str, InlineSpan)>,
+    new: Vec<(&str, InlineSpan)>,
+) -> (Vec<InlineSpan>, Vec<InlineSpan>) {
+    // lcs[i][j] is the LCS length of old[i..] and new[j..].
+    let mut lcs = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i].0 == new[j].0 {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let (mut removed, mut added) = (vec![], vec![]);
+    let (mut old, mut new) = (old.into_iter().peekable(), new.into_iter().peekable());
+    let (mut i, mut j) = (0, 0);
+    while let (Some(o), Some(n)) = (old.peek(), new.peek()) {
+        if o.0 == n.0 {
+            old.next();
+            new.next();
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            removed.push(old.next().unwrap().1);
+            i += 1;
+        } else {
+            added.push(new.next().unwrap().1);
+            j += 1;
+        }
+    }
+    removed.extend(old.map(|(_, span)| span));
+    added.extend(new.map(|(_, span)| span));
+    (removed, added)
+}
+
+impl ChunkDiff {
+    /// Pairs each run of deleted lines with the added lines that follow it and
+    /// finds the words that changed between them, for highlighted rendering.
+    pub fn inline_changes(&self) -> Vec<InlineChange> {
+        let run_end = |from: usize, action: fn(&DiffAction) -> bool| {
+            from + self.lines[from..]
+                .iter()
+                .take_while(|line| action(&line.action))
+                .count()
+        };
+        let is_delete: fn(&DiffAction) -> bool = |action| matches!(action, DiffAction::Delete);
+        let is_add: fn(&DiffAction) -> bool = |action| matches!(action, DiffAction::Add);
+
+        let mut changes = vec![];
+        let mut idx = 0;
+        while idx < self.lines.len() {
+            if !is_delete(&self.lines[idx].action) {
+                idx += 1;
+                continue;
+            }
+            let old_lines = idx..run_end(idx, is_delete);
+            let new_lines = old_lines.end..run_end(old_lines.end, is_add);
+            idx = new_lines.end;
+            if new_lines.is_empty() {
+                continue;
+            }
+
+            let (removed, added) = unmatched(
+                words(self, old_lines.clone()),
+                words(self, new_lines.clone()),
+            );
+            changes.push(InlineChange {
+                old_lines,
+                new_lines,
+                removed,
+                added,
+            });
+        }
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(line: usize, start: usize, end: usize) -> InlineSpan {
+        InlineSpan { line, start, end }
+    }
+
+    #[test]
+    fn word_changes() {
+        let input = "diff --git a/f b/f\nindex 1111111..2222222 100644\n--- a/f\n+++ b/f\n\
+                     @@ -1,7 +1,7 @@\n-same words\n+same words\n ctx\n-let x = 1;\n+let x = 2;\n \
+                     ctx\n-alpha beta\n+gamma delta\n";
+        let diffs = crate::parse(input).unwrap();
+        let changes = diffs[0].chunks[0].inline_changes();
+        assert_eq!(changes.len(), 3);
+
+        // An identical line has no changed words.
+        assert_eq!(changes[0].old_lines, 0..1);
+        assert_eq!(changes[0].new_lines, 1..2);
+        assert!(changes[0].removed.is_empty() && changes[0].added.is_empty());
+
+        // Only the word that differs.
+        assert_eq!(changes[1].removed, [span(3, 8, 10)]);
+        assert_eq!(changes[1].added, [span(4, 8, 10)]);
+
+        // Every word of a fully replaced line.
+        assert_eq!(changes[2].removed, [span(6, 0, 5), span(6, 6, 10)]);
+        assert_eq!(changes[2].added, [span(7, 0, 5), span(7, 6, 11)]);
+    }
+}
@@ -0,0 +1,63 @@
+use alloc::boxed::Box;
+use core::marker::PhantomData;
+use core::ops::Deref;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+// A value built on first use, for statics that need the heap, such as the
+// regexes. Needs only atomics, not `std`: threads that race on the first use
+// each build the value and all but the first throw theirs away.
+pub(crate) struct Lazy<T> {
+    cell: AtomicPtr<T>,
+    init: fn() -> T,
+    _owns: PhantomData<*mut T>,
+}
+
+// Shared `&T`s come out of `deref`, and the value may be built on one thread
+// and dropped on another.
+unsafe impl<T: Send + Sync> Sync for Lazy<T> {}
+
+impl<T> Lazy<T> {
+    pub(crate) const fn new(init: fn() -> T) -> Self {
+        Lazy {
+            cell: AtomicPtr::new(ptr::null_mut()),
+            init,
+            _owns: PhantomData,
+        }
+    }
+}
+
+impl<T> Deref for Lazy<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        let mut value = self.cell.load(Ordering::Acquire);
+        if value.is_null() {
+            let built = Box::into_raw(Box::new((self.init)()));
+            value = match self.cell.compare_exchange(
+                ptr::null_mut(),
+                built,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => built,
+                Err(winner) => {
+                    // Another thread got there first; `built` was never shared.
+                    drop(unsafe { Box::from_raw(built) });
+                    winner
+                }
+            };
+        }
+        // Once set, the pointer never changes until `self` is dropped.
+        unsafe { &*value }
+    }
+}
+
+impl<T> Drop for Lazy<T> {
+    fn drop(&mut self) {
+        let value = *self.cell.get_mut();
+        if !value.is_null() {
+            drop(unsafe { Box::from_raw(value) });
+        }
+    }
+}
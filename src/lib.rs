@@ -1,2 +1,506 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
 pub mod aggregator;
+pub mod apply;
+pub mod builder;
+pub mod inline;
+mod lazy;
 pub mod line_parser;
+pub mod patch;
+pub mod raw;
+pub mod scanner;
+#[cfg(feature = "tokio")]
+pub mod stream;
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::{self, Vec};
+use core::fmt;
+use core::ops::Index;
+use core::slice;
+use core::str::FromStr;
+
+use aggregator::{Aggregator, FileDiff};
+use line_parser::{Grammar, LineKind, LineMachine, LineParser, ParseError, ParserConfig};
+
+/// Parses a whole `git diff` output, accepting both `\n` and `\r\n` line endings.
+/// Blank and `#` comment lines before the first file are skipped, so empty input
+/// gives no files rather than an error.
+/// Like git, only `\n` ends a line: a lone `\r`, as in old Mac text files, is
+/// part of the line's content. To split lines differently, give the lines to
+/// [`DiffParser::new`] instead.
+pub fn parse(input: &str) -> Result<Vec<FileDiff>, ParseError> {
+    DiffParser::new(lines(input)).collect()
+}
+
+/// Like [`parse`], but doesn't stop at the first error: the file an error is in
+/// is dropped and parsing resumes at the next `diff` header, so every problem
+/// can be reported at once along with the files that did parse.
+pub fn try_parse(input: &str) -> (Vec<FileDiff>, Vec<ParseError>) {
+    let mut machine = LineMachine::new(Grammar::default());
+    let mut aggregator = Aggregator::new();
+    let mut diffs = Vec::new();
+    let mut errors = Vec::new();
+    let mut recovering = false;
+    for line in lines(input) {
+        if recovering {
+            if !line.starts_with("diff ") {
+                machine.skip();
+                continue;
+            }
+            recovering = false;
+            machine.resync();
+        }
+        let step = machine.step(line);
+        let mut pushed = push_step(&mut aggregator, &machine, step);
+        if pushed.is_err() && line.starts_with("diff ") {
+            // The "diff" line that cut a file short still starts the next one.
+            if let Some(step) = machine.retry(line) {
+                errors.extend(pushed.err());
+                aggregator.finish();
+                pushed = push_step(&mut aggregator, &machine, step);
+            }
+        }
+        match pushed {
+            Ok(Some(file_diff)) => diffs.push(file_diff),
+            Ok(None) => {}
+            Err(err) => {
+                errors.push(err);
+                aggregator.finish();
+                recovering = true;
+            }
+        }
+    }
+    diffs.extend(aggregator.finish());
+    (diffs, errors)
+}
+
+// Aggregates the line `machine` just stepped over.
+fn push_step(
+    aggregator: &mut Aggregator,
+    machine: &LineMachine,
+    step: Result<Option<LineKind>, ParseError>,
+) -> Result<Option<FileDiff>, ParseError> {
+    match step {
+        Ok(Some(kind)) => aggregate(aggregator, &kind, machine.line_number()),
+        Ok(None) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Like [`parse`], but with custom `--src-prefix` / `--dst-prefix` path prefixes.
+pub fn parse_with_config(input: &str, config: ParserConfig) -> Result<Vec<FileDiff>, ParseError> {
+    DiffParser::with_config(lines(input), config).collect()
+}
+
+// Like `str::lines`, but leaves a `\r` before the `\n` for the line parser to
+// strip or keep.
+pub(crate) fn lines(input: &str) -> impl Iterator<Item = &str> {
+    input
+        .split_inclusive('\n')
+        .map(|line| line.strip_suffix('\n').unwrap_or(line))
+}
+
+/// Every file of a parsed diff, so a diff can be read with `input.parse::<Diff>()`.
+///
+/// ```
+/// use gitdiffparser::Diff;
+///
+/// let input = "diff --git a/f b/f\nindex 1111111..2222222 100644\n--- a/f\n+++ b/f\n\
+///              @@ -1 +1 @@\n-a\n+b\n";
+/// let diff: Diff = input.parse().unwrap();
+/// for file in &diff {
+///     println!("{} -> {}", file.from.file, file.to.file);
+/// }
+/// assert_eq!(diff.len(), 1);
+/// ```
+#[derive(Debug)]
+pub struct Diff {
+    pub files: Vec<FileDiff>,
+}
+
+impl FromStr for Diff {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Diff { files: parse(s)? })
+    }
+}
+
+impl Diff {
+    /// Number of files in the diff.
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, FileDiff> {
+        self.files.iter()
+    }
+}
+
+impl IntoIterator for Diff {
+    type Item = FileDiff;
+    type IntoIter = vec::IntoIter<FileDiff>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.files.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Diff {
+    type Item = &'a FileDiff;
+    type IntoIter = slice::Iter<'a, FileDiff>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.files.iter()
+    }
+}
+
+impl Index<usize> for Diff {
+    type Output = FileDiff;
+
+    fn index(&self, idx: usize) -> &FileDiff {
+        &self.files[idx]
+    }
+}
+
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for file in &self.files {
+            write!(f, "{}", file)?;
+        }
+        Ok(())
+    }
+}
+
+/// Splits `input` into the text of each file's diff, from its header to just
+/// before the next file's, so they can be stored or applied independently.
+/// Lines before the first file are left out.
+pub fn split_files(input: &str) -> Result<Vec<&str>, ParseError> {
+    let mut starts: Vec<usize> = line_parser::parse_lines_with_offsets(input)?
+        .into_iter()
+        .filter(|(parsed, _)| {
+            matches!(
+                parsed.kind,
+                LineKind::FileDiffHeader(_)
+                    | LineKind::CombinedFileDiffHeader(_)
+                    | LineKind::PlainAFileChangeHeader(_)
+            )
+        })
+        .map(|(_, span)| span.start)
+        .collect();
+    starts.push(input.len());
+    Ok(starts
+        .windows(2)
+        .map(|bounds| &input[bounds[0]..bounds[1]])
+        .collect())
+}
+
+/// Total (additions, deletions) across files, like `git diff --shortstat`.
+pub fn total_stats(diffs: &[FileDiff]) -> (usize, usize) {
+    diffs
+        .iter()
+        .map(FileDiff::stats)
+        .fold((0, 0), |(a, d), (fa, fd)| (a + fa, d + fd))
+}
+
+/// Keeps the files whose old or new path matches a glob `pattern`, so a rename
+/// is kept if either side matches. `?` and `*` match within a path component and
+/// `**` across them; a pattern without a `/` is matched against file names only.
+pub fn filter_by_path(diffs: Vec<FileDiff>, pattern: &str) -> Vec<FileDiff> {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let matches = |path: &str| {
+        let path = if pattern.contains(&'/') {
+            path
+        } else {
+            path.rsplit('/').next().unwrap_or(path)
+        };
+        glob_match(&pattern, &path.chars().collect::<Vec<_>>())
+    };
+    diffs
+        .into_iter()
+        .filter(|diff| matches(&diff.from.file) || matches(&diff.to.file))
+        .collect()
+}
+
+/// Groups files by the directory of their new path (the old one for deletions),
+/// with `""` for the top level, e.g. to show the changes as a tree.
+pub fn group_by_directory(diffs: &[FileDiff]) -> BTreeMap<String, Vec<&FileDiff>> {
+    let mut groups: BTreeMap<String, Vec<&FileDiff>> = BTreeMap::new();
+    for diff in diffs {
+        let path = diff.new_path().unwrap_or(&diff.from.file);
+        let dir = path.rsplit_once('/').map_or("", |(dir, _)| dir);
+        groups.entry(dir.to_string()).or_default().push(diff);
+    }
+    groups
+}
+
+fn glob_match(pattern: &[char], path: &[char]) -> bool {
+    match pattern {
+        [] => path.is_empty(),
+        ['*', '*', rest @ ..] => {
+            // "**/" may also match no directories at all.
+            let skips_dirs = matches!(rest, ['/', after @ ..] if glob_match(after, path));
+            skips_dirs || (0..=path.len()).any(|i| glob_match(rest, &path[i..]))
+        }
+        ['*', rest @ ..] => {
+            let component = path.iter().position(|&c| c == '/').unwrap_or(path.len());
+            (0..=component).any(|i| glob_match(rest, &path[i..]))
+        }
+        ['?', rest @ ..] => matches!(path, [c, tail @ ..] if *c != '/' && glob_match(rest, tail)),
+        [p, rest @ ..] => matches!(path, [c, tail @ ..] if c == p && glob_match(rest, tail)),
+    }
+}
+
+// Feeds a parsed line to the aggregator, numbering its errors. Shared by
+// `DiffParser`, `DiffScanner` and the async `DiffStream`.
+pub(crate) fn aggregate(
+    aggregator: &mut Aggregator,
+    kind: &LineKind,
+    line_number: usize,
+) -> Result<Option<FileDiff>, ParseError> {
+    aggregator
+        .push_at(kind, line_number)
+        .map_err(|err| ParseError::AggregateError(line_number, err))
+}
+
+/// Lazily yields each `FileDiff` as soon as the next `diff --git` header (or the
+/// end of input) is reached, so large diffs never have to be held in memory.
+pub struct DiffParser<I> {
+    lines: LineParser<I>,
+    aggregator: Aggregator,
+    done: bool,
+}
+
+impl<I: Iterator> DiffParser<I> {
+    pub fn new(lines: I) -> Self {
+        Self::with_lines(LineParser::new(lines))
+    }
+
+    pub fn with_config(lines: I, config: ParserConfig) -> Self {
+        Self::with_lines(LineParser::with_config(lines, config))
+    }
+
+    fn with_lines(lines: LineParser<I>) -> Self {
+        DiffParser {
+            lines,
+            aggregator: Aggregator::new(),
+            done: false,
+        }
+    }
+}
+
+impl<I> Iterator for DiffParser<I>
+where
+    I: Iterator,
+    I::Item: AsRef<str>,
+{
+    type Item = Result<FileDiff, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        while let Some(parsed) = self.lines.next() {
+            let state = match parsed {
+                Ok(parsed) => parsed.kind,
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err));
+                }
+            };
+            match aggregate(&mut self.aggregator, &state, self.lines.line_number()) {
+                Ok(Some(file_diff)) => return Some(Ok(file_diff)),
+                Ok(None) => {}
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+
+        self.done = true;
+        self.aggregator.finish().map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::format;
+
+    use super::*;
+
+    const VALID: &str = "diff --git a/g b/g\nindex 1111111..2222222 100644\n--- a/g\n+++ b/g\n\
+                         @@ -1 +1 @@\n-a\n+b\n";
+
+    #[test]
+    fn try_parse_skips_a_broken_hunk() {
+        let input = alloc::format!(
+            "diff --git a/f b/f\nindex 1111111..2222222 100644\n--- a/f\n+++ b/f\n\
+             @@ -1,2 +1,2 @@\n-a\nbogus\n+b\n{VALID}"
+        );
+        let (diffs, errors) = try_parse(&input);
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(diffs[0].to.file, "g");
+        assert_eq!(diffs[0].chunks, parse(VALID).unwrap()[0].chunks);
+        assert!(matches!(
+            errors[..],
+            [ParseError::Unexpected { line: 7, .. }]
+        ));
+    }
+
+    #[test]
+    fn try_parse_keeps_the_file_a_failing_header_starts() {
+        let input = alloc::format!("diff --git a/f b/f\nold mode 100644\n{VALID}");
+        let (diffs, errors) = try_parse(&input);
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(diffs[0].to.file, "g");
+        assert_eq!(diffs[0].source_line, 3);
+        assert!(matches!(
+            errors[..],
+            [ParseError::Unexpected { line: 3, .. }]
+        ));
+    }
+
+    #[test]
+    fn input_without_files() {
+        for input in ["", "\n", "  \n\t\n", "# just a comment\n", "# a\n\n# b"] {
+            assert_eq!(parse(input).unwrap(), [], "{input:?}");
+        }
+    }
+
+    #[test]
+    fn comments_before_the_first_file() {
+        let input = alloc::format!("# generated\n\n{VALID}");
+        let diffs = parse(&input).unwrap();
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(diffs[0].source_line, 3);
+    }
+
+    #[test]
+    fn lone_cr_stays_in_the_line() {
+        let input = "diff --git a/mac.txt b/mac.txt\nindex 1111111..2222222 100644\n\
+                     --- a/mac.txt\n+++ b/mac.txt\n@@ -1 +1 @@\n-one\rtwo\rthree\n+one\r2\r\n";
+        let diffs = parse(input).unwrap();
+        let lines: Vec<_> = diffs[0].chunks[0]
+            .lines
+            .iter()
+            .map(|line| (line.line.as_str(), line.trailing_cr))
+            .collect();
+        assert_eq!(lines, [("one\rtwo\rthree", false), ("one\r2", true)]);
+        assert_eq!(diffs[0].stats(), (1, 1));
+    }
+
+    const TREE: &str = "diff --git a/src/main.rs b/src/main.rs\nindex 1111111..2222222 100644\n\
+                        --- a/src/main.rs\n+++ b/src/main.rs\n@@ -1 +1 @@\n-a\n+b\n\
+                        diff --git a/src/parser/mod.rs b/src/parser/mod.rs\n\
+                        index 3333333..4444444 100644\n--- a/src/parser/mod.rs\n\
+                        +++ b/src/parser/mod.rs\n@@ -1 +1 @@\n-c\n+d\n\
+                        diff --git a/README.md b/README.md\nindex 5555555..6666666 100644\n\
+                        --- a/README.md\n+++ b/README.md\n@@ -1 +1 @@\n-e\n+f\n\
+                        diff --git a/old/x.rs b/docs/x.txt\nsimilarity index 100%\n\
+                        rename from old/x.rs\nrename to docs/x.txt\n\
+                        diff --git a/lib/z.c b/lib/z.c\ndeleted file mode 100644\n\
+                        index 7777777..0000000\n--- a/lib/z.c\n+++ /dev/null\n@@ -1 +0,0 @@\n-g\n";
+
+    fn filtered(pattern: &str) -> Vec<String> {
+        filter_by_path(parse(TREE).unwrap(), pattern)
+            .into_iter()
+            .map(|diff| diff.to.file)
+            .collect()
+    }
+
+    #[test]
+    fn path_filters() {
+        assert_eq!(filtered("src/*.rs"), ["src/main.rs"]);
+        assert_eq!(
+            filtered("src/**/*.rs"),
+            ["src/main.rs", "src/parser/mod.rs"]
+        );
+        assert_eq!(filtered("**/mod.rs"), ["src/parser/mod.rs"]);
+        assert_eq!(filtered("src/ma?n.rs"), ["src/main.rs"]);
+        assert_eq!(filtered("src?main.rs"), Vec::<String>::new());
+        // Without a "/", only file names are matched, in any directory.
+        assert_eq!(
+            filtered("*.rs"),
+            ["src/main.rs", "src/parser/mod.rs", "docs/x.txt"]
+        );
+        assert_eq!(filtered("README.md"), ["README.md"]);
+        // A rename is kept when either side matches.
+        assert_eq!(filtered("docs/*.txt"), ["docs/x.txt"]);
+        assert_eq!(filtered("old/**"), ["docs/x.txt"]);
+        assert_eq!(filtered("lib/*.c"), ["lib/z.c"]);
+    }
+
+    #[test]
+    fn directory_groups() {
+        let diffs = parse(TREE).unwrap();
+        let groups = group_by_directory(&diffs);
+        let dirs: Vec<&str> = groups.keys().map(String::as_str).collect();
+        assert_eq!(dirs, ["", "docs", "lib", "src", "src/parser"]);
+        assert!(groups.values().all(|files| files.len() == 1));
+        // A rename goes under its new directory.
+        assert_eq!(groups["docs"][0].from.file, "old/x.rs");
+        // A deletion has no new path, so it goes under its old one.
+        assert!(groups["lib"][0].to.is_dev_null);
+        assert_eq!(groups["lib"][0].from.file, "lib/z.c");
+    }
+
+    #[test]
+    fn diff_parser_yields_each_file_then_stops_at_an_error() {
+        use core::cell::Cell;
+
+        let input = "diff --git a/f b/f\nindex 1111111..2222222 100644\n--- a/f\n+++ b/f\n\
+                     @@ -1 +1 @@\n-a\n+b\n\
+                     diff --git a/g b/g\nindex 3333333..4444444 100644\nnot a header\n\
+                     diff --git a/h b/h\nindex 5555555..6666666 100644\n--- a/h\n+++ b/h\n\
+                     @@ -1 +1 @@\n-c\n+d\n";
+        let read = Cell::new(0);
+        let mut parser = DiffParser::new(lines(input).inspect(|_| read.set(read.get() + 1)));
+
+        // The first file comes out once the second one's header is read.
+        assert_eq!(parser.next().unwrap().unwrap().to.file, "f");
+        assert_eq!(read.get(), 8);
+        assert!(matches!(
+            parser.next(),
+            Some(Err(ParseError::Unexpected { line: 10, .. }))
+        ));
+        assert!(parser.next().is_none());
+        assert_eq!(read.get(), 10);
+    }
+
+    #[test]
+    fn byte_spans_cover_the_input() {
+        let note = "# leading note\r\n";
+        let input = format!(
+            "{note}diff --git a/f b/f\r\nindex 1111111..2222222 100644\r\n--- a/f\r\n\
+             +++ b/f\r\n@@ -1 +1 @@\r\n-a\r\n+b\r\n\
+             diff --git a/g b/g\nindex 3333333..4444444 100644\n--- a/g\n+++ b/g\n\
+             @@ -1 +1 @@\n-c\n+d"
+        );
+        let lines = line_parser::parse_lines_with_offsets(&input).unwrap();
+        // Skipped lines have no span; the others, with their "\n", tile the rest.
+        let mut end = note.len();
+        for (parsed, span) in &lines {
+            assert_eq!(span.start, end);
+            assert_eq!(&input[span.clone()], parsed.raw());
+            end = span.end + 1;
+        }
+        assert_eq!(end - 1, input.len());
+        assert_eq!(lines[0].1, 16..35);
+        assert_eq!(&input[lines[0].1.clone()], "diff --git a/f b/f\r");
+
+        let files = split_files(&input).unwrap();
+        assert_eq!(files.len(), 2);
+        assert!(files[0].starts_with("diff --git a/f") && files[0].ends_with("+b\r\n"));
+        assert!(files[1].starts_with("diff --git a/g") && files[1].ends_with("+d"));
+        assert_eq!(files.concat(), input[note.len()..]);
+    }
+}
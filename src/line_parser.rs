@@ -1,274 +1,1256 @@
-use lazy_static::lazy_static;
 use regex::Regex;
 
-use std::collections::HashMap;
-use std::fmt;
-use std::iter::Iterator;
-use std::string::ToString;
-
-lazy_static! {
-    static ref FILE_DIFF_HEADER: regex::Regex =
-        Regex::new(r"^diff --git a/(?P<from_file>.*?)\s* b/(?P<to_file>.*?)\s*$").unwrap();
-    static ref OLD_MODE_HEADER: regex::Regex = Regex::new(r"^old mode (?P<mode>\d+)$").unwrap();
-    static ref NEW_MODE_HEADER: regex::Regex = Regex::new(r"^new mode (?P<mode>\d+)$").unwrap();
-    static ref NEW_FILE_MODE_HEADER: regex::Regex = Regex::new(r"^new file mode (?P<mode>\d+)$").unwrap();
-    static ref DELETED_FILE_MODE_HEADER: regex::Regex = Regex::new(r"^deleted file mode (?P<mode>\d+)$").unwrap();
-    static ref INDEX_DIFF_HEADER: regex::Regex = Regex::new(r"^index (?P<from_blob>.*?)\.\.(?P<to_blob>.*?)(?: (?P<mode>\d+))?$").unwrap();
-    static ref BINARY_DIFF: regex::Regex = Regex::new(r"Binary files (?P<from_file>.*) and (?P<to_file>.*) differ$").unwrap();
-    static ref A_FILE_CHANGE_HEADER: regex::Regex = Regex::new(r"^--- (?:/dev/null|a/(?P<file>.*?)\s*)$").unwrap();
-    static ref B_FILE_CHANGE_HEADER: regex::Regex = Regex::new(r"^\+\+\+ (?:/dev/null|b/(?P<file>.*?)\s*)$").unwrap();
-    static ref CHUNK_HEADER: regex::Regex = Regex::new(r"^@@ -(?P<from_line_start>\d+)(?:,(?P<from_line_count>\d+))? \+(?P<to_line_start>\d+)(?:,(?P<to_line_count>\d+))? @@(?P<line>.*)$").unwrap();
-
-    static ref LINE_DIFF: regex::Regex = Regex::new(r"^(?P<action>[-+ ])(?P<line>.*)$").unwrap();
-    static ref NO_NEWLINE: regex::Regex = Regex::new(r"^\\ No newline at end of file$").unwrap();
-    static ref RENAME_HEADER: regex::Regex = Regex::new(r"^similarity index (?P<rate>\d*)").unwrap();
-    static ref RENAME_A_FILE: regex::Regex = Regex::new(r"^rename from (?P<from_file>.*?)").unwrap();
-    static ref RENAME_B_FILE: regex::Regex = Regex::new(r"^rename to (?P<to_file>.*?)").unwrap();
+use crate::aggregator::{AggregateError, DiffAction, LinePoint};
+use crate::lazy::Lazy;
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::iter::Iterator;
+use core::ops::Range;
+
+static COMBINED_FILE_DIFF_HEADER: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^diff --(?:cc|combined) (?P<file>.*?)\s*$").unwrap());
+static OLD_MODE_HEADER: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^old mode (?P<mode>\d+)$").unwrap());
+static NEW_MODE_HEADER: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^new mode (?P<mode>\d+)$").unwrap());
+static NEW_FILE_MODE_HEADER: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^new file mode (?P<mode>\d+)$").unwrap());
+static DELETED_FILE_MODE_HEADER: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^deleted file mode (?P<mode>\d+)$").unwrap());
+static INDEX_DIFF_HEADER: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^index (?P<from_blob>.*?)\.\.(?P<to_blob>.*?)(?: (?P<mode>\d+))?$").unwrap()
+});
+static BINARY_DIFF: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"Binary files (?P<from_file>.*) and (?P<to_file>.*) differ$").unwrap()
+});
+static GIT_BINARY_PATCH: Lazy<Regex> = Lazy::new(|| Regex::new(r"^GIT binary patch$").unwrap());
+static BINARY_PATCH_SIZE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^(?:literal|delta) \d+$").unwrap());
+static BINARY_PATCH_DATA: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^(?:[A-Za-z][0-9A-Za-z!#$%&()*+;<=>?@^_`{|}~-]+)?$").unwrap());
+static CHUNK_HEADER: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^@@ -(?P<from_line_start>\d+)(?:,(?P<from_line_count>\d+))? \+(?P<to_line_start>\d+)(?:,(?P<to_line_count>\d+))? @@(?P<line>.*)$").unwrap()
+});
+static COMBINED_CHUNK_HEADER: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^(?P<marker>@{3,}) (?P<from_ranges>-\d+(?:,\d+)?(?: -\d+(?:,\d+)?)+) \+(?P<to_line_start>\d+)(?:,(?P<to_line_count>\d+))? @{3,}(?P<line>.*)$").unwrap()
+});
+
+static NO_NEWLINE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^\\ No newline at end of file$").unwrap());
+static RENAME_HEADER: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^similarity index (?P<rate>\d+)%$").unwrap());
+static RENAME_A_FILE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^rename from (?P<from_file>.*)$").unwrap());
+static RENAME_B_FILE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^rename to (?P<to_file>.*)$").unwrap());
+static DISSIMILARITY_HEADER: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^dissimilarity index (?P<rate>\d+)%$").unwrap());
+static COPY_A_FILE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^copy from (?P<from_file>.*)$").unwrap());
+static COPY_B_FILE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^copy to (?P<to_file>.*)$").unwrap());
+static DEFAULT_GRAMMAR: Lazy<Grammar> = Lazy::new(|| Grammar::new(ParserConfig::default()));
+
+// A C-style quoted path body, without the surrounding double quotes.
+const QUOTED: &str = r#"(?:[^"\\]|\\.)*"#;
+
+// The (source, destination) prefixes `diff.mnemonicPrefix` uses: commit, index,
+// worktree and object. A path starting with one of them alone is a real path.
+const MNEMONIC_PREFIXES: [(&str, &str); 4] =
+    [("i/", "w/"), ("c/", "w/"), ("c/", "i/"), ("o/", "w/")];
+
+/// Options for input that differs from a default `git diff`.
+#[derive(Debug, Clone)]
+pub struct ParserConfig {
+    // Path prefixes expected in headers, as set by `--src-prefix` / `--dst-prefix`.
+    pub src_prefix: String,
+    pub dst_prefix: String,
+    // When off, unrecognized lines before the first file and between `diff --git`
+    // and the first `@@` are skipped, `---` may follow any header and GNU diff's
+    // `diff <options> <a> <b>` lines separate files, so GNU-style diffs parse
+    // too, and a file whose headers use a pair of `diff.mnemonicPrefix`
+    // prefixes, such as `i/` and `w/`, has them stripped as well.
+    pub strict: bool,
+    // Keep the `\r` of CRLF-terminated content lines; headers always drop it.
+    pub preserve_cr: bool,
+}
+
+impl Default for ParserConfig {
+    fn default() -> Self {
+        ParserConfig {
+            src_prefix: "a/".to_string(),
+            dst_prefix: "b/".to_string(),
+            strict: true,
+            preserve_cr: false,
+        }
+    }
+}
+
+// The regexes that depend on the configured prefixes, built once per config.
+#[derive(Debug, Clone)]
+pub(crate) struct Grammar {
+    file_diff_header: Regex,
+    a_file_change_header: Regex,
+    b_file_change_header: Regex,
+    src_prefix: String,
+    dst_prefix: String,
+    strict: bool,
+    preserve_cr: bool,
+    // In lenient mode, a grammar for each pair of `MNEMONIC_PREFIXES`.
+    mnemonics: Vec<Grammar>,
+}
+
+impl Grammar {
+    pub(crate) fn new(config: ParserConfig) -> Self {
+        let mnemonics = if config.strict {
+            vec![]
+        } else {
+            MNEMONIC_PREFIXES
+                .iter()
+                .map(|(src, dst)| {
+                    Grammar::with_prefixes(ParserConfig {
+                        src_prefix: src.to_string(),
+                        dst_prefix: dst.to_string(),
+                        ..config.clone()
+                    })
+                })
+                .collect()
+        };
+        Grammar {
+            mnemonics,
+            ..Grammar::with_prefixes(config)
+        }
+    }
+
+    fn with_prefixes(config: ParserConfig) -> Self {
+        let src = regex::escape(&config.src_prefix);
+        let dst = regex::escape(&config.dst_prefix);
+        Grammar {
+            file_diff_header: Regex::new(&format!(
+                r#"^diff --git (?:"(?:{src})?(?P<quoted_from_file>{q})"|(?:{src})?(?P<from_file>.*?))\s* (?:"(?:{dst})?(?P<quoted_to_file>{q})"|(?:{dst})?(?P<to_file>.*?))\s*$"#,
+                src = src,
+                dst = dst,
+                q = QUOTED,
+            ))
+            .unwrap(),
+            a_file_change_header: Regex::new(&format!(
+                r#"^--- (?:/dev/null|"(?:{src})?(?P<quoted_file>{q})"|(?:{src})?(?P<file>[^\t]*?))\s*(?:\t(?P<timestamp>.*))?$"#,
+                src = src,
+                q = QUOTED,
+            ))
+            .unwrap(),
+            b_file_change_header: Regex::new(&format!(
+                r#"^\+\+\+ (?:/dev/null|"(?:{dst})?(?P<quoted_file>{q})"|(?:{dst})?(?P<file>[^\t]*?))\s*(?:\t(?P<timestamp>.*))?$"#,
+                dst = dst,
+                q = QUOTED,
+            ))
+            .unwrap(),
+            src_prefix: config.src_prefix,
+            dst_prefix: config.dst_prefix,
+            strict: config.strict,
+            preserve_cr: config.preserve_cr,
+            mnemonics: vec![],
+        }
+    }
+
+    // The grammar for the file a "diff --git" line starts: the one of the
+    // mnemonic pair its paths start with, if they don't use the configured
+    // prefixes. `None` for this grammar.
+    fn file_grammar(&self, line: &str) -> Option<usize> {
+        let rest = line.strip_prefix("diff --git ")?;
+        let starts_with = |prefix: &str| {
+            rest.starts_with(prefix)
+                || rest
+                    .strip_prefix('"')
+                    .is_some_and(|rest| rest.starts_with(prefix))
+        };
+        let has_second = |prefix: &str| {
+            rest.contains(&format!(" {}", prefix)) || rest.contains(&format!(" \"{}", prefix))
+        };
+        if starts_with(&self.src_prefix) && has_second(&self.dst_prefix) {
+            return None;
+        }
+        self.mnemonics
+            .iter()
+            .position(|grammar| starts_with(&grammar.src_prefix) && has_second(&grammar.dst_prefix))
+    }
+
+    // The paths of an unquoted "diff --git" line split so that both sides are the
+    // same, if there is such a split. A path holding " b/" defeats the regex, but
+    // unless the file was renamed this finds where the second path starts.
+    fn symmetric_paths(&self, line: &str) -> Option<(String, String)> {
+        let rest = line.strip_prefix("diff --git ")?.trim_end();
+        let separator = format!(" {}", self.dst_prefix);
+        rest.match_indices(&separator).find_map(|(idx, _)| {
+            let from = rest[..idx].strip_prefix(self.src_prefix.as_str())?;
+            let to = &rest[idx + separator.len()..];
+            (from == to).then(|| (from.to_string(), to.to_string()))
+        })
+    }
+
+    // How many places an unquoted "diff --git" line could be split at.
+    fn separators(&self, line: &str) -> usize {
+        let separator = format!(" {}", self.dst_prefix);
+        line.trim_end().matches(&separator).count()
+    }
+
+    // Whether `line` is the "diff <options> <a> <b>" line GNU diff writes before
+    // each file, accepted in lenient mode wherever a new file may start.
+    fn is_gnu_diff_header(&self, line: &str, prev_state: &LineKind) -> bool {
+        !self.strict
+            && line.starts_with("diff ")
+            && !line.starts_with("diff --git ")
+            && !line.starts_with("diff --cc ")
+            && !line.starts_with("diff --combined ")
+            && (starts_file(prev_state) || is_file_header(prev_state))
+    }
+
+    // Whether a line that failed to parse after `prev_state` can be dropped.
+    pub(crate) fn skips_unrecognized(&self, prev_state: &LineKind) -> bool {
+        !self.strict && (is_file_header(prev_state) || matches!(prev_state, LineKind::StartOfFile))
+    }
+}
+
+// Whether a "---" line after `prev_state` starts a new file rather than
+// deleting a line that begins with "--".
+fn ends_chunk(prev_state: &LineKind) -> bool {
+    match prev_state {
+        LineKind::StartOfFile | LineKind::NoNewline => true,
+        LineKind::LineDiff(prev) => prev.remaining == Some((0, 0)),
+        _ => false,
+    }
+}
+
+// States a `diff` line starting the next file may follow.
+fn starts_file(state: &LineKind) -> bool {
+    matches!(
+        state,
+        LineKind::StartOfFile
+            | LineKind::NewModeHeader(_)
+            | LineKind::LineDiff(_)
+            | LineKind::NoNewline
+            | LineKind::IndexDiffHeader(_)
+            | LineKind::BinaryDiff
+            | LineKind::BinaryPatchLine(_)
+            | LineKind::RenameBFile(_)
+            | LineKind::CopyBFile(_)
+    )
+}
+
+// States between a `diff --git` line and the first `@@`.
+fn is_file_header(state: &LineKind) -> bool {
+    matches!(
+        state,
+        LineKind::FileDiffHeader(_)
+            | LineKind::CombinedFileDiffHeader(_)
+            | LineKind::OldModeHeader(_)
+            | LineKind::NewModeHeader(_)
+            | LineKind::NewFileModeHeader(_)
+            | LineKind::DeletedFileModeHeader(_)
+            | LineKind::RenameHeader(_)
+            | LineKind::RenameAFile(_)
+            | LineKind::RenameBFile(_)
+            | LineKind::DissimilarityHeader(_)
+            | LineKind::CopyAFile(_)
+            | LineKind::CopyBFile(_)
+            | LineKind::IndexDiffHeader(_)
+            | LineKind::AFileChangeHeader(_)
+            | LineKind::BFileChangeHeader(_)
+    )
+}
+
+impl Default for Grammar {
+    fn default() -> Self {
+        DEFAULT_GRAMMAR.clone()
+    }
+}
+
+/// The kind of line the parser was looking for when a line didn't parse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpectedLine {
+    FileDiffHeader,
+    NewModeHeader,
+    IndexDiffHeader,
+    RenameOrCopyFrom,
+    RenameTo,
+    CopyTo,
+    BinaryPatchData,
+    AFileChangeHeader,
+    BFileChangeHeader,
+    ChunkHeader,
+    // A combined chunk header with as many ranges as its "@@@" marker says.
+    CombinedChunkRanges,
+    // A line number that fits in a `usize`.
+    LineNumber,
+    LineDiffOrNoNewline,
+    RawDiffEntry,
+}
+
+impl fmt::Display for ExpectedLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let expected = match self {
+            ExpectedLine::FileDiffHeader => "file diff header",
+            ExpectedLine::NewModeHeader => "new_mode_header",
+            ExpectedLine::IndexDiffHeader => "index_diff_header",
+            ExpectedLine::RenameOrCopyFrom => "rename from or copy from",
+            ExpectedLine::RenameTo => "rename to",
+            ExpectedLine::CopyTo => "copy to",
+            ExpectedLine::BinaryPatchData => "binary patch data",
+            ExpectedLine::AFileChangeHeader => "a_file_change_header",
+            ExpectedLine::BFileChangeHeader => "b_file_change_header",
+            ExpectedLine::ChunkHeader => "chunk_header",
+            ExpectedLine::CombinedChunkRanges => "one range per parent in combined chunk_header",
+            ExpectedLine::LineNumber => "a valid line number",
+            ExpectedLine::LineDiffOrNoNewline => "line_diff or no_newline",
+            ExpectedLine::RawDiffEntry => "raw diff entry",
+        };
+        write!(f, "expected {}", expected)
+    }
 }
 
 #[derive(Debug)]
 pub enum ParseError {
-    Expected(String),
-    LineParseError(usize, String),
+    // Line `line` (1-based), whose text is `found`, isn't a line that can come next.
+    Unexpected {
+        line: usize,
+        expected: ExpectedLine,
+        found: String,
+    },
+    AggregateError(usize, AggregateError),
+    // Line `.0` of pushed bytes isn't valid UTF-8, in `DiffScanner`.
+    Utf8(usize, core::str::Utf8Error),
+    // Reading the input failed, in the async `DiffStream`.
+    #[cfg(feature = "std")]
+    Io(std::io::Error),
 }
 
 impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            ParseError::Expected(s) => write!(f, "{}", s),
-            ParseError::LineParseError(n, s) => write!(f, "Line: {}: {}", n, s),
+            ParseError::Unexpected {
+                line,
+                expected,
+                found,
+            } => write!(f, "Line: {}: {}: {:?}", line, expected, found),
+            ParseError::AggregateError(n, err) => write!(f, "Line: {}: {}", n, err),
+            ParseError::Utf8(n, err) => write!(f, "Line: {}: {}", n, err),
+            #[cfg(feature = "std")]
+            ParseError::Io(err) => write!(f, "{}", err),
         }
     }
 }
 
-fn captures_to_map(re: &Regex, text: &str) -> HashMap<String, String> {
-    let caps = re.captures(text).unwrap();
-    re.capture_names()
-        .flatten()
-        .filter_map(|n| Some((n.to_string(), caps.name(n)?.as_str().to_string())))
-        .collect()
+impl core::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            ParseError::Unexpected { .. } => None,
+            ParseError::AggregateError(_, err) => Some(err),
+            ParseError::Utf8(_, err) => Some(err),
+            #[cfg(feature = "std")]
+            ParseError::Io(err) => Some(err),
+        }
+    }
 }
 
-type ParseR = (String, HashMap<String, String>);
+// The named groups `re` captures in `text`, or `None` if it doesn't match.
+fn captures_to_map(re: &Regex, text: &str) -> Option<BTreeMap<String, String>> {
+    let caps = re.captures(text)?;
+    Some(
+        re.capture_names()
+            .flatten()
+            .filter_map(|n| Some((n.to_string(), caps.name(n)?.as_str().to_string())))
+            .collect(),
+    )
+}
 
-fn parse_line(line: String, prev_state: &str) -> Result<ParseR, ParseError> {
-    let states = [
-        "start_of_file",
-        "new_mode_header",
-        "line_diff",
-        "no_newline",
-        "index_diff_header",
-        "binary_diff",
-        "rename_b_file",
-    ];
+#[derive(Debug, Clone)]
+pub struct FileHeaderData {
+    pub from_file: String,
+    pub to_file: String,
+    // The split between the paths is certain: a path is quoted, both are the
+    // same, or only one " b/" separates them.
+    pub exact: bool,
+}
 
-    if states.contains(&prev_state) {
-        if FILE_DIFF_HEADER.is_match(&line) {
-            let mode = "file_diff_header".to_string();
-            let captures = captures_to_map(&FILE_DIFF_HEADER, &line);
-            return Ok((mode, captures));
-        } else if prev_state == "start_of_file" {
-            return Err(ParseError::Expected(
-                "expected file diff header".to_string(),
-            ));
+#[derive(Debug, Clone)]
+pub struct FileChangeHeaderData {
+    // `None` when the side is /dev/null.
+    pub file: Option<String>,
+    // What GNU diff prints after a tab, usually the modification time.
+    pub timestamp: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct IndexData {
+    pub from_blob: String,
+    pub to_blob: String,
+    pub mode: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChunkHeaderData {
+    pub from: LinePoint,
+    pub to: LinePoint,
+    pub section: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct CombinedChunkHeaderData {
+    pub parents: Vec<LinePoint>,
+    pub to: LinePoint,
+    pub section: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct LineDiffData {
+    pub action: DiffAction,
+    pub line: String,
+    // (old, new) lines the chunk header still expects after this one; `None`
+    // in combined diffs.
+    pub remaining: Option<(usize, usize)>,
+    // The line ended in "\r\n", whether or not `line` keeps the `\r`.
+    pub trailing_cr: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum LineKind {
+    StartOfFile,
+    FileDiffHeader(FileHeaderData),
+    CombinedFileDiffHeader(String),
+    OldModeHeader(String),
+    NewModeHeader(String),
+    NewFileModeHeader(String),
+    DeletedFileModeHeader(String),
+    RenameHeader(String),
+    RenameAFile(String),
+    RenameBFile(String),
+    DissimilarityHeader(String),
+    CopyAFile(String),
+    CopyBFile(String),
+    IndexDiffHeader(IndexData),
+    BinaryDiff,
+    GitBinaryPatch,
+    // A "literal"/"delta" size line, a base85 data line or the blank line ending a block.
+    BinaryPatchLine(String),
+    AFileChangeHeader(FileChangeHeaderData),
+    // A "---" line starting a file in a plain unified diff, with no `diff --git`.
+    PlainAFileChangeHeader(FileChangeHeaderData),
+    BFileChangeHeader(FileChangeHeaderData),
+    ChunkHeader(ChunkHeaderData),
+    CombinedChunkHeader(CombinedChunkHeaderData),
+    LineDiff(LineDiffData),
+    NoNewline,
+}
+
+fn take(captures: &mut BTreeMap<String, String>, name: &str) -> String {
+    captures.remove(name).unwrap_or_default()
+}
+
+// Prefers the "quoted_{name}" capture, decoding its escapes, over the plain one.
+fn take_path(captures: &mut BTreeMap<String, String>, name: &str) -> Option<String> {
+    match captures.remove(&format!("quoted_{}", name)) {
+        Some(quoted) => Some(unescape(&quoted)),
+        None => captures.remove(name),
+    }
+}
+
+fn file_change_header(mut captures: BTreeMap<String, String>) -> FileChangeHeaderData {
+    FileChangeHeaderData {
+        file: take_path(&mut captures, "file"),
+        timestamp: captures.remove("timestamp"),
+    }
+}
+
+fn unescape(escaped: &str) -> String {
+    let mut bytes = vec![];
+    let mut chars = escaped.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('a') => bytes.push(0x07),
+            Some('b') => bytes.push(0x08),
+            Some('t') => bytes.push(b'\t'),
+            Some('n') => bytes.push(b'\n'),
+            Some('v') => bytes.push(0x0b),
+            Some('f') => bytes.push(0x0c),
+            Some('r') => bytes.push(b'\r'),
+            Some(d @ '0'..='7') => {
+                let mut byte = d.to_digit(8).unwrap();
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(8)) {
+                        Some(digit) => {
+                            byte = byte * 8 + digit;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                bytes.push(byte as u8);
+            }
+            Some(other) => {
+                let mut buf = [0; 4];
+                bytes.extend_from_slice(other.encode_utf8(&mut buf).as_bytes());
+            }
+            None => bytes.push(b'\\'),
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Decodes a path git wrapped in double quotes with C-style escapes, such as
+/// `"na\303\257ve.txt"`. Unquoted paths are returned unchanged.
+pub fn unquote_path(path: &str) -> String {
+    match path
+        .strip_prefix('"')
+        .and_then(|path| path.strip_suffix('"'))
+    {
+        Some(quoted) => unescape(quoted),
+        None => path.to_string(),
+    }
+}
+
+/// The inverse of [`unquote_path`]: quotes a path the way git does when it holds
+/// a double quote, a backslash, a control character or a non-ASCII byte.
+pub fn quote_path(path: &str) -> String {
+    let needs_quoting = path
+        .bytes()
+        .any(|b| b == b'"' || b == b'\\' || !(b' '..=b'~').contains(&b));
+    if !needs_quoting {
+        return path.to_string();
+    }
+
+    let mut quoted = String::from("\"");
+    for b in path.bytes() {
+        match b {
+            0x07 => quoted.push_str("\\a"),
+            0x08 => quoted.push_str("\\b"),
+            b'\t' => quoted.push_str("\\t"),
+            b'\n' => quoted.push_str("\\n"),
+            0x0b => quoted.push_str("\\v"),
+            0x0c => quoted.push_str("\\f"),
+            b'\r' => quoted.push_str("\\r"),
+            b'"' => quoted.push_str("\\\""),
+            b'\\' => quoted.push_str("\\\\"),
+            b if !(b' '..=b'~').contains(&b) => quoted.push_str(&format!("\\{:03o}", b)),
+            b => quoted.push(b as char),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn parse_line_number(n: &str) -> Result<usize, ExpectedLine> {
+    n.parse().map_err(|_| ExpectedLine::LineNumber)
+}
+
+fn line_point(start: &str, count: Option<&str>) -> Result<LinePoint, ExpectedLine> {
+    Ok(LinePoint {
+        line_start: parse_line_number(start)?,
+        line_count: count.map_or(Ok(1), parse_line_number)?,
+    })
+}
+
+// "-{START}[,{COUNT}]"
+fn parse_range(range: &str) -> Result<LinePoint, ExpectedLine> {
+    let range = range.trim_start_matches('-');
+    match range.split_once(',') {
+        Some((start, count)) => line_point(start, Some(count)),
+        None => line_point(range, None),
+    }
+}
+
+fn index_data(line: &str) -> Option<LineKind> {
+    let mut captures = captures_to_map(&INDEX_DIFF_HEADER, line)?;
+    Some(LineKind::IndexDiffHeader(IndexData {
+        from_blob: take(&mut captures, "from_blob"),
+        to_blob: take(&mut captures, "to_blob"),
+        mode: captures.remove("mode"),
+    }))
+}
+
+pub(crate) fn parse_line(
+    line: &str,
+    prev_state: &LineKind,
+    grammar: &Grammar,
+) -> Result<LineKind, ExpectedLine> {
+    if starts_file(prev_state) {
+        // Most lines here are hunk content, so the header regexes only run on
+        // lines that start like a header.
+        if line.starts_with("diff --") {
+            if let Some((from_file, to_file)) = grammar.symmetric_paths(line) {
+                return Ok(LineKind::FileDiffHeader(FileHeaderData {
+                    from_file,
+                    to_file,
+                    exact: true,
+                }));
+            } else if let Some(mut captures) = captures_to_map(&grammar.file_diff_header, line) {
+                let quoted = captures.contains_key("quoted_from_file")
+                    || captures.contains_key("quoted_to_file");
+                return Ok(LineKind::FileDiffHeader(FileHeaderData {
+                    from_file: take_path(&mut captures, "from_file").unwrap_or_default(),
+                    to_file: take_path(&mut captures, "to_file").unwrap_or_default(),
+                    exact: quoted || grammar.separators(line) == 1,
+                }));
+            } else if let Some(mut captures) = captures_to_map(&COMBINED_FILE_DIFF_HEADER, line) {
+                return Ok(LineKind::CombinedFileDiffHeader(take(
+                    &mut captures,
+                    "file",
+                )));
+            }
+        }
+        if ends_chunk(prev_state) && line.starts_with("--- ") {
+            if let Some(captures) = captures_to_map(&grammar.a_file_change_header, line) {
+                return Ok(LineKind::PlainAFileChangeHeader(file_change_header(
+                    captures,
+                )));
+            }
+        }
+        if matches!(prev_state, LineKind::StartOfFile) {
+            return Err(ExpectedLine::FileDiffHeader);
+        }
+    }
+
+    // "--- {FILENAME}" without an index line, accepted in lenient mode only
+    if !grammar.strict
+        && is_file_header(prev_state)
+        && !matches!(
+            prev_state,
+            LineKind::AFileChangeHeader(_) | LineKind::BFileChangeHeader(_)
+        )
+    {
+        if let Some(captures) = captures_to_map(&grammar.a_file_change_header, line) {
+            return Ok(LineKind::AFileChangeHeader(file_change_header(captures)));
         }
     }
 
-    // "old mode {MODE}"
-    if prev_state == "file_diff_header" {
-        if OLD_MODE_HEADER.is_match(&line) {
-            let mode = "old_mode_header".to_string();
-            let captures = captures_to_map(&OLD_MODE_HEADER, &line);
-            return Ok((mode, captures));
+    // "old mode {MODE}", which some tools write after the rename or copy lines
+    // rather than before them, as git does.
+    if matches!(
+        prev_state,
+        LineKind::FileDiffHeader(_) | LineKind::RenameBFile(_) | LineKind::CopyBFile(_)
+    ) {
+        if let Some(mut captures) = captures_to_map(&OLD_MODE_HEADER, line) {
+            return Ok(LineKind::OldModeHeader(take(&mut captures, "mode")));
         }
     }
 
     // "new mode {MODE}"
-    if prev_state == "old_mode_header" {
-        if NEW_MODE_HEADER.is_match(&line) {
-            let mode = "new_mode_header".to_string();
-            let captures = captures_to_map(&NEW_MODE_HEADER, &line);
-            return Ok((mode, captures));
+    if matches!(prev_state, LineKind::OldModeHeader(_)) {
+        if let Some(mut captures) = captures_to_map(&NEW_MODE_HEADER, line) {
+            return Ok(LineKind::NewModeHeader(take(&mut captures, "mode")));
         } else {
-            return Err(ParseError::Expected("expected new_mode_header".to_string()));
+            return Err(ExpectedLine::NewModeHeader);
         }
     }
 
     // "new file mode {MODE}"
-    if prev_state == "file_diff_header" {
-        if NEW_FILE_MODE_HEADER.is_match(&line) {
-            let mode = "new_file_mode_header".to_string();
-            let captures = captures_to_map(&NEW_FILE_MODE_HEADER, &line);
-            return Ok((mode, captures));
+    if matches!(prev_state, LineKind::FileDiffHeader(_)) {
+        if let Some(mut captures) = captures_to_map(&NEW_FILE_MODE_HEADER, line) {
+            return Ok(LineKind::NewFileModeHeader(take(&mut captures, "mode")));
         }
     }
 
     // "deleted file mode {MODE}"
-    if prev_state == "file_diff_header" {
-        if DELETED_FILE_MODE_HEADER.is_match(&line) {
-            let mode = "deleted_file_mode_header".to_string();
-            let captures = captures_to_map(&DELETED_FILE_MODE_HEADER, &line);
-            return Ok((mode, captures));
+    if matches!(prev_state, LineKind::FileDiffHeader(_)) {
+        if let Some(mut captures) = captures_to_map(&DELETED_FILE_MODE_HEADER, line) {
+            return Ok(LineKind::DeletedFileModeHeader(take(&mut captures, "mode")));
         }
     }
 
+    // "index {FROM_COMMIT},{FROM_COMMIT}..{TO_COMMIT}"
+    if matches!(prev_state, LineKind::CombinedFileDiffHeader(_)) {
+        return index_data(line).ok_or(ExpectedLine::IndexDiffHeader);
+    }
+
     // "index {FROM_COMMIT} {TO_COMMIT} [{MODE}]"
-    if [
-        "rename_b_file",
-        "file_diff_header",
-        "new_mode_header",
-        "new_file_mode_header",
-        "deleted_file_mode_header",
-    ]
-    .contains(&prev_state)
-    {
-        if RENAME_HEADER.is_match(&line) {
-            let mode = "rename_header".to_string();
-            let captures = captures_to_map(&RENAME_HEADER, &line);
-            return Ok((mode, captures));
+    if matches!(
+        prev_state,
+        LineKind::RenameBFile(_)
+            | LineKind::CopyBFile(_)
+            | LineKind::DissimilarityHeader(_)
+            | LineKind::FileDiffHeader(_)
+            | LineKind::NewModeHeader(_)
+            | LineKind::NewFileModeHeader(_)
+            | LineKind::DeletedFileModeHeader(_)
+    ) {
+        if let Some(mut captures) = captures_to_map(&RENAME_HEADER, line) {
+            return Ok(LineKind::RenameHeader(take(&mut captures, "rate")));
         }
 
-        if INDEX_DIFF_HEADER.is_match(&line) {
-            let mode = "index_diff_header".to_string();
-            let captures = captures_to_map(&INDEX_DIFF_HEADER, &line);
-            return Ok((mode, captures));
-        } else {
-            return Err(ParseError::Expected(
-                "expected index_diff_header".to_string(),
-            ));
+        if let Some(mut captures) = captures_to_map(&DISSIMILARITY_HEADER, line) {
+            return Ok(LineKind::DissimilarityHeader(take(&mut captures, "rate")));
+        }
+
+        return index_data(line).ok_or(ExpectedLine::IndexDiffHeader);
+    }
+
+    if matches!(prev_state, LineKind::RenameHeader(_)) {
+        if let Some(mut captures) = captures_to_map(&RENAME_A_FILE, line) {
+            return Ok(LineKind::RenameAFile(unquote_path(&take(
+                &mut captures,
+                "from_file",
+            ))));
+        }
+    }
+
+    if matches!(prev_state, LineKind::RenameAFile(_)) {
+        if let Some(mut captures) = captures_to_map(&RENAME_B_FILE, line) {
+            return Ok(LineKind::RenameBFile(unquote_path(&take(
+                &mut captures,
+                "to_file",
+            ))));
         }
     }
 
-    if prev_state == "rename_header" {
-        if RENAME_A_FILE.is_match(&line) {
-            let mode = "rename_a_file".to_string();
-            let captures = captures_to_map(&RENAME_A_FILE, &line);
-            return Ok((mode, captures));
+    if matches!(prev_state, LineKind::RenameHeader(_)) {
+        if let Some(mut captures) = captures_to_map(&COPY_A_FILE, line) {
+            return Ok(LineKind::CopyAFile(unquote_path(&take(
+                &mut captures,
+                "from_file",
+            ))));
         }
     }
 
-    if prev_state == "rename_a_file" {
-        if RENAME_B_FILE.is_match(&line) {
-            let mode = "rename_b_file".to_string();
-            let captures = captures_to_map(&RENAME_B_FILE, &line);
-            return Ok((mode, captures));
+    if matches!(prev_state, LineKind::CopyAFile(_)) {
+        if let Some(mut captures) = captures_to_map(&COPY_B_FILE, line) {
+            return Ok(LineKind::CopyBFile(unquote_path(&take(
+                &mut captures,
+                "to_file",
+            ))));
         }
     }
 
     // "Binary files {FROM_FILE} and {TO_FILE} differ"
-    if prev_state == "index_diff_header" {
-        if BINARY_DIFF.is_match(&line) {
-            let mode = "binary_diff".to_string();
-            let captures = captures_to_map(&BINARY_DIFF, &line);
-            return Ok((mode, captures));
+    if matches!(prev_state, LineKind::IndexDiffHeader(_)) && BINARY_DIFF.is_match(line) {
+        return Ok(LineKind::BinaryDiff);
+    }
+
+    // "GIT binary patch"
+    if matches!(prev_state, LineKind::IndexDiffHeader(_)) && GIT_BINARY_PATCH.is_match(line) {
+        return Ok(LineKind::GitBinaryPatch);
+    }
+
+    // "literal {SIZE}"
+    // "delta {SIZE}"
+    // "{LENGTH}{BASE85 DATA}"
+    if matches!(
+        prev_state,
+        LineKind::GitBinaryPatch | LineKind::BinaryPatchLine(_)
+    ) {
+        if BINARY_PATCH_SIZE.is_match(line) || BINARY_PATCH_DATA.is_match(line) {
+            return Ok(LineKind::BinaryPatchLine(line.to_string()));
+        } else {
+            return Err(ExpectedLine::BinaryPatchData);
         }
     }
 
     // "--- {FILENAME}"
-    if prev_state == "index_diff_header" {
-        if A_FILE_CHANGE_HEADER.is_match(&line) {
-            let mode = "a_file_change_header".to_string();
-            let captures = captures_to_map(&A_FILE_CHANGE_HEADER, &line);
-            return Ok((mode, captures));
+    if matches!(prev_state, LineKind::IndexDiffHeader(_)) {
+        if let Some(captures) = captures_to_map(&grammar.a_file_change_header, line) {
+            return Ok(LineKind::AFileChangeHeader(file_change_header(captures)));
         } else {
-            return Err(ParseError::Expected(
-                "expected a_file_change_header".to_string(),
-            ));
+            return Err(ExpectedLine::AFileChangeHeader);
         }
     }
 
     // "+++ {FILENAME}"
-    if prev_state == "a_file_change_header" {
-        if B_FILE_CHANGE_HEADER.is_match(line.as_str()) {
-            let mode = "b_file_change_header".to_string();
-            let captures = captures_to_map(&B_FILE_CHANGE_HEADER, &line);
-            return Ok((mode, captures));
+    if matches!(
+        prev_state,
+        LineKind::AFileChangeHeader(_) | LineKind::PlainAFileChangeHeader(_)
+    ) {
+        if let Some(captures) = captures_to_map(&grammar.b_file_change_header, line) {
+            return Ok(LineKind::BFileChangeHeader(file_change_header(captures)));
         } else {
-            return Err(ParseError::Expected(
-                "expected b_file_change_header".to_string(),
-            ));
+            return Err(ExpectedLine::BFileChangeHeader);
         }
     }
 
     // "@@ {?}[,{?}] {?}[,{?}] @@[{LINE}]"
-    if ["b_file_change_header", "line_diff", "no_newline"].contains(&prev_state) {
-        if CHUNK_HEADER.is_match(line.as_str()) {
-            let mut captures = captures_to_map(&CHUNK_HEADER, &line);
-            if !captures.contains_key("from_line_count") {
-                captures.insert("from_line_count".to_string(), "1".to_string());
-            }
-            if !captures.contains_key("to_line_count") {
-                captures.insert("to_line_count".to_string(), "1".to_string());
-            }
-            if !captures.contains_key("to_line_start") {
-                let from_line_start = captures.get("from_line_start").unwrap();
-                captures.insert("to_line_start".to_string(), from_line_start.to_string());
+    if matches!(
+        prev_state,
+        LineKind::BFileChangeHeader(_) | LineKind::LineDiff(_) | LineKind::NoNewline
+    ) && line.starts_with("@@")
+    {
+        if let Some(mut captures) = captures_to_map(&CHUNK_HEADER, line) {
+            let from = line_point(
+                &take(&mut captures, "from_line_start"),
+                captures.get("from_line_count").map(String::as_str),
+            )?;
+            let to = line_point(
+                &take(&mut captures, "to_line_start"),
+                captures.get("to_line_count").map(String::as_str),
+            )?;
+            return Ok(LineKind::ChunkHeader(ChunkHeaderData {
+                from,
+                to,
+                section: take(&mut captures, "line"),
+            }));
+        } else if let Some(mut captures) = captures_to_map(&COMBINED_CHUNK_HEADER, line) {
+            let parents = captures
+                .get("from_ranges")
+                .unwrap()
+                .split(' ')
+                .map(parse_range)
+                .collect::<Result<Vec<_>, _>>()?;
+            if captures.get("marker").unwrap().len() != parents.len() + 1 {
+                return Err(ExpectedLine::CombinedChunkRanges);
             }
-            let from_line_start = captures.get("from_line_start").unwrap();
-            captures.insert("from_line_start".to_string(), from_line_start.to_string());
-            let from_line_count = captures.get("from_line_count").unwrap();
-            captures.insert("from_line_count".to_string(), from_line_count.to_string());
-            let to_line_start = captures.get("to_line_start").unwrap();
-            captures.insert("to_line_start".to_string(), to_line_start.to_string());
-            let to_line_count = captures.get("to_line_count").unwrap();
-            captures.insert("to_line_count".to_string(), to_line_count.to_string());
-            let mode = "chunk_header".to_string();
-            return Ok((mode, captures));
-        } else if prev_state == "b_file_change_header" {
-            return Err(ParseError::Expected("expected chunk_header".to_string()));
+            let to = line_point(
+                &take(&mut captures, "to_line_start"),
+                captures.get("to_line_count").map(String::as_str),
+            )?;
+            return Ok(LineKind::CombinedChunkHeader(CombinedChunkHeaderData {
+                parents,
+                to,
+                section: take(&mut captures, "line"),
+            }));
         }
     }
+    if matches!(prev_state, LineKind::BFileChangeHeader(_)) {
+        return Err(ExpectedLine::ChunkHeader);
+    }
 
     // "-{LINE}"
     // "+{LINE}"
     // " {LINE}"
-    if ["chunk_header", "line_diff", "no_newline"].contains(&prev_state) {
-        if LINE_DIFF.is_match(line.as_str()) {
-            let mode = "line_diff".to_string();
-            let captures = captures_to_map(&LINE_DIFF, &line);
-            return Ok((mode, captures));
+    // "{ACTION}{ACTION}...{LINE}" in combined diffs
+    if matches!(
+        prev_state,
+        LineKind::ChunkHeader(_)
+            | LineKind::CombinedChunkHeader(_)
+            | LineKind::LineDiff(_)
+            | LineKind::NoNewline
+    ) && line.starts_with(['-', '+', ' '])
+    {
+        let action = match line.as_bytes()[0] {
+            b'-' => DiffAction::Delete,
+            b'+' => DiffAction::Add,
+            prefix => {
+                debug_assert_eq!(prefix, b' ');
+                DiffAction::Context
+            }
+        };
+        let remaining = match prev_state {
+            LineKind::ChunkHeader(header) => Some((header.from.line_count, header.to.line_count)),
+            LineKind::LineDiff(prev) => prev.remaining,
+            // Only lines of the side that still has a newline can follow a marker.
+            LineKind::NoNewline => Some((0, 0)),
+            _ => None,
         }
+        .map(|(old, new)| match action {
+            DiffAction::Delete => (old.saturating_sub(1), new),
+            DiffAction::Add => (old, new.saturating_sub(1)),
+            DiffAction::Context => (old.saturating_sub(1), new.saturating_sub(1)),
+        });
+        return Ok(LineKind::LineDiff(LineDiffData {
+            action,
+            line: line[1..].to_string(),
+            remaining,
+            trailing_cr: false,
+        }));
     }
 
     // "\ No newline at end of file"
-    if ["chunk_header", "line_diff"].contains(&prev_state) {
-        if NO_NEWLINE.is_match(line.as_str()) {
-            let mode = "no_newline".to_string();
-            let captures = captures_to_map(&NO_NEWLINE, &line);
-            return Ok((mode, captures));
+    if matches!(
+        prev_state,
+        LineKind::ChunkHeader(_) | LineKind::CombinedChunkHeader(_) | LineKind::LineDiff(_)
+    ) {
+        if NO_NEWLINE.is_match(line) {
+            return Ok(LineKind::NoNewline);
         } else {
-            return Err(ParseError::Expected(
-                "expected line_diff or no_newline".to_string(),
-            ));
+            return Err(ExpectedLine::LineDiffOrNoNewline);
         }
     }
 
-    return Err(ParseError::Expected(format!(
-        "can't parse line with prev_state {:?}",
-        prev_state
-    )));
+    // The states left are those where only one kind of line may follow.
+    Err(match prev_state {
+        LineKind::RenameHeader(_) => ExpectedLine::RenameOrCopyFrom,
+        LineKind::RenameAFile(_) => ExpectedLine::RenameTo,
+        LineKind::CopyAFile(_) => ExpectedLine::CopyTo,
+        _ => ExpectedLine::FileDiffHeader,
+    })
 }
 
-type ParsedLines = Vec<(String, HashMap<String, String>, String)>;
+type ParsedLines = Vec<ParsedLine>;
+
+type BorrowedParsedLines<'a> = Vec<ParsedLine<&'a str>>;
+
+type OffsetParsedLines<'a> = Vec<(ParsedLine<&'a str>, Range<usize>)>;
 
 type ParseLinesResult = Result<ParsedLines, ParseError>;
 
-pub fn parse_lines(line_iterable: impl Iterator<Item = impl ToString>) -> ParseLinesResult {
-    let mut state = "start_of_file".to_string();
-    let mut parses = vec![];
-    for (line_idx, line) in line_iterable.enumerate() {
-        let prev_state = state.clone();
-
-        //println!("prev_state: {:?} line: {:?}", prev_state, line);
-        match parse_line(line.to_string().clone(), &prev_state) {
-            Ok((n_state, parsed)) => {
-                state = n_state.clone();
-                parses.push((n_state, parsed, line.to_string()));
+/// A parsed line: its `LineKind`, whose payload holds the fields captured from
+/// the line, the raw line it came from and that line's 1-based number in the input.
+#[derive(Debug, Clone)]
+pub struct ParsedLine<L = String> {
+    pub kind: LineKind,
+    pub raw: L,
+    pub line_number: usize,
+}
+
+impl<L: AsRef<str>> ParsedLine<L> {
+    pub fn kind(&self) -> &LineKind {
+        &self.kind
+    }
+
+    pub fn raw(&self) -> &str {
+        self.raw.as_ref()
+    }
+
+    pub fn line_number(&self) -> usize {
+        self.line_number
+    }
+
+    pub fn into_kind(self) -> LineKind {
+        self.kind
+    }
+}
+
+// The per-line state machine, fed one line at a time by both `LineParser` and
+// the async `DiffStream`.
+#[derive(Debug)]
+pub(crate) struct LineMachine {
+    state: LineKind,
+    grammar: Grammar,
+    // Index of the mnemonic grammar the current file's headers use.
+    mnemonic: Option<usize>,
+    line_number: usize,
+}
+
+impl LineMachine {
+    pub(crate) fn new(grammar: Grammar) -> Self {
+        LineMachine {
+            state: LineKind::StartOfFile,
+            grammar,
+            mnemonic: None,
+            line_number: 0,
+        }
+    }
+
+    // 1-based number of the line most recently read.
+    pub(crate) fn line_number(&self) -> usize {
+        self.line_number
+    }
+
+    // Starts over at the first line of a new diff.
+    pub(crate) fn reset(&mut self) {
+        self.state = LineKind::StartOfFile;
+        self.mnemonic = None;
+        self.line_number = 0;
+    }
+
+    // Skips a line without parsing it, as when recovering from an error.
+    pub(crate) fn skip(&mut self) {
+        self.line_number += 1;
+    }
+
+    // Expects the first line of a file next, keeping the line count.
+    pub(crate) fn resync(&mut self) {
+        self.state = LineKind::StartOfFile;
+        self.mnemonic = None;
+    }
+
+    // Steps `raw`, the line that just failed mid-file, again as the first line of
+    // a file. `None` if it already failed as the first line of a file.
+    pub(crate) fn retry(&mut self, raw: &str) -> Option<Result<Option<LineKind>, ParseError>> {
+        if matches!(self.state, LineKind::StartOfFile) {
+            return None;
+        }
+        self.line_number -= 1;
+        self.resync();
+        Some(self.step(raw))
+    }
+
+    // Parses the next line, without its "\n"; `Ok(None)` when the line is skipped.
+    pub(crate) fn step(&mut self, raw: &str) -> Result<Option<LineKind>, ParseError> {
+        self.line_number += 1;
+        let text = raw.strip_suffix('\r').unwrap_or(raw);
+        // Blank and "#" comment lines before the first file are not an error.
+        if matches!(self.state, LineKind::StartOfFile)
+            && (text.trim().is_empty() || text.starts_with('#'))
+        {
+            return Ok(None);
+        }
+        // Only separates files: the "---" line after it starts the next one.
+        if self.grammar.is_gnu_diff_header(text, &self.state) {
+            self.resync();
+            return Ok(None);
+        }
+        if text.starts_with("diff ") && starts_file(&self.state) {
+            self.mnemonic = self.grammar.file_grammar(text);
+        }
+        let grammar = match self.mnemonic {
+            Some(idx) => &self.grammar.mnemonics[idx],
+            None => &self.grammar,
+        };
+        match parse_line(text, &self.state, grammar) {
+            Ok(mut kind) => {
+                if let LineKind::LineDiff(ref mut data) = kind {
+                    data.trailing_cr = text.len() < raw.len();
+                    if self.grammar.preserve_cr && data.trailing_cr {
+                        data.line.push('\r');
+                    }
+                }
+                self.state = kind.clone();
+                Ok(Some(kind))
             }
-            Err(_err) => {
-                return Err(ParseError::LineParseError(line_idx + 1, line.to_string()));
+            Err(_) if self.grammar.skips_unrecognized(&self.state) => Ok(None),
+            Err(expected) => Err(ParseError::Unexpected {
+                line: self.line_number,
+                expected,
+                found: raw.to_string(),
+            }),
+        }
+    }
+}
+
+/// Lazily parses each line into a [`ParsedLine`], so the line parser can feed the
+/// aggregator without collecting first. `parse_lines(iter)` is equivalent to
+/// `LineParser::new(iter.map(|line| line.to_string())).collect()`.
+pub struct LineParser<I> {
+    iter: I,
+    machine: LineMachine,
+    done: bool,
+}
+
+impl<I: Iterator> LineParser<I> {
+    pub fn new(iter: I) -> Self {
+        Self::with_grammar(iter, Grammar::default())
+    }
+
+    pub fn with_config(iter: I, config: ParserConfig) -> Self {
+        Self::with_grammar(iter, Grammar::new(config))
+    }
+
+    pub(crate) fn with_grammar(iter: I, grammar: Grammar) -> Self {
+        LineParser {
+            iter,
+            machine: LineMachine::new(grammar),
+            done: false,
+        }
+    }
+
+    // 1-based number of the line most recently read.
+    pub(crate) fn line_number(&self) -> usize {
+        self.machine.line_number()
+    }
+}
+
+impl<I> Iterator for LineParser<I>
+where
+    I: Iterator,
+    I::Item: AsRef<str>,
+{
+    type Item = Result<ParsedLine<I::Item>, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        for line in self.iter.by_ref() {
+            match self.machine.step(line.as_ref()) {
+                Ok(Some(kind)) => {
+                    return Some(Ok(ParsedLine {
+                        kind,
+                        raw: line,
+                        line_number: self.machine.line_number(),
+                    }))
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err));
+                }
             }
         }
+        None
+    }
+}
+
+pub fn parse_lines(line_iterable: impl Iterator<Item = impl ToString>) -> ParseLinesResult {
+    LineParser::new(line_iterable.map(|line| line.to_string())).collect()
+}
+
+pub fn parse_lines_with_config(
+    line_iterable: impl Iterator<Item = impl ToString>,
+    config: ParserConfig,
+) -> ParseLinesResult {
+    LineParser::with_config(line_iterable.map(|line| line.to_string()), config).collect()
+}
+
+/// Like `parse_lines`, but keeps each raw line as a slice of `input` instead of
+/// copying it into an owned `String`.
+pub fn parse_lines_borrowed(input: &str) -> Result<BorrowedParsedLines<'_>, ParseError> {
+    LineParser::new(crate::lines(input)).collect()
+}
+
+/// Like `parse_lines_borrowed`, but pairs each line with the byte range it spans in
+/// `input`, e.g. to jump to a hunk in an editor. The range leaves out the `\n` but,
+/// like [`ParsedLine::raw`], keeps the `\r` of a `\r\n` ending.
+pub fn parse_lines_with_offsets(input: &str) -> Result<OffsetParsedLines<'_>, ParseError> {
+    LineParser::new(crate::lines(input))
+        .map(|parsed| {
+            parsed.map(|parsed| {
+                let offset = parsed.raw.as_ptr() as usize - input.as_ptr() as usize;
+                let span = offset..offset + parsed.raw.len();
+                (parsed, span)
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregator::ChangeType;
+
+    #[test]
+    fn whitespace_only_lines_keep_their_action() {
+        let input = "diff --git a/f b/f\nindex 1111111..2222222 100644\n--- a/f\n+++ b/f\n\
+                     @@ -1,2 +1,2 @@\n-\n+ \n \n";
+        let lines: Vec<_> = parse_lines_borrowed(input)
+            .unwrap()
+            .into_iter()
+            .filter_map(|parsed| match parsed.kind {
+                LineKind::LineDiff(data) => Some((data.action.as_str(), data.line)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                ("-", String::new()),
+                ("+", " ".to_string()),
+                (" ", String::new())
+            ]
+        );
+    }
+
+    #[test]
+    fn quoted_and_spaced_paths() {
+        let input = "diff --git a/my file.txt b/my file.txt\nindex 1111111..2222222 100644\n\
+                     --- a/my file.txt\n+++ b/my file.txt\n@@ -1 +1 @@\n-a\n+b\n\
+                     diff --git \"a/caf\\303\\251.txt\" \"b/caf\\303\\251.txt\"\n\
+                     index 3333333..4444444 100644\n--- \"a/caf\\303\\251.txt\"\n\
+                     +++ \"b/caf\\303\\251.txt\"\n@@ -1 +1 @@\n-c\n+d\n\
+                     diff --git \"a/say \\\"hi\\\".txt\" \"b/say \\\"hi\\\".txt\"\n\
+                     index 5555555..6666666 100644\n--- \"a/say \\\"hi\\\".txt\"\n\
+                     +++ \"b/say \\\"hi\\\".txt\"\n@@ -1 +1 @@\n-e\n+f\n";
+        let diffs = crate::parse(input).unwrap();
+        let paths: Vec<_> = diffs
+            .iter()
+            .map(|diff| (diff.from.file.as_str(), diff.to.file.as_str()))
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                ("my file.txt", "my file.txt"),
+                ("café.txt", "café.txt"),
+                ("say \"hi\".txt", "say \"hi\".txt"),
+            ]
+        );
+    }
+
+    fn lenient() -> ParserConfig {
+        ParserConfig {
+            strict: false,
+            ..ParserConfig::default()
+        }
+    }
+
+    #[test]
+    fn gnu_diff_needs_lenient_mode() {
+        let input = "diff -u a/f b/f\n--- a/f\t2024-01-01 00:00:00.000000000 +0000\n\
+                     +++ b/f\t2024-01-02 00:00:00.000000000 +0000\n@@ -1 +1 @@\n-a\n+b\n";
+        assert!(matches!(
+            crate::parse(input),
+            Err(ParseError::Unexpected { line: 1, .. })
+        ));
+        let diffs = crate::parse_with_config(input, lenient()).unwrap();
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(diffs[0].to.file, "f");
+        assert_eq!(
+            diffs[0].to.timestamp.as_deref(),
+            Some("2024-01-02 00:00:00.000000000 +0000")
+        );
+    }
+
+    #[test]
+    fn gnu_diff_lines_separate_files() {
+        let input = "diff -ruN a/f b/f\n--- a/f\t2024-01-01\n+++ b/f\t2024-01-02\n\
+                     @@ -1 +1 @@\n-a\n+b\ndiff -ruN a/g b/g\n--- a/g\t2024-01-01\n\
+                     +++ b/g\t2024-01-02\n@@ -1 +1 @@\n-c\n+d\n";
+        assert!(crate::parse(input).is_err());
+        let diffs = crate::parse_with_config(input, lenient()).unwrap();
+        let paths: Vec<_> = diffs.iter().map(|diff| diff.to.file.as_str()).collect();
+        assert_eq!(paths, ["f", "g"]);
+        assert_eq!(diffs[1].source_line, 8);
+    }
+
+    #[test]
+    fn lenient_mode_skips_unknown_header_lines() {
+        let input = "diff --git a/f b/f\nextended header\nindex 1111111..2222222 100644\n\
+                     --- a/f\n+++ b/f\n@@ -1 +1 @@\n-a\n+b\n";
+        assert!(matches!(
+            crate::parse(input),
+            Err(ParseError::Unexpected { line: 2, .. })
+        ));
+        assert_eq!(crate::parse_with_config(input, lenient()).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn lenient_mode_strips_mnemonic_prefix_pairs() {
+        let input = "diff --git i/src/main.c w/src/main.c\nindex 1111111..2222222 100644\n\
+                     --- i/src/main.c\n+++ w/src/main.c\n@@ -1 +1 @@\n-a\n+b\n";
+        let diffs = crate::parse_with_config(input, lenient()).unwrap();
+        assert_eq!(diffs[0].from.file, "src/main.c");
+        assert_eq!(diffs[0].to.file, "src/main.c");
+        let diffs = crate::parse(input).unwrap();
+        assert_eq!(diffs[0].from.file, "i/src/main.c");
+        assert_eq!(diffs[0].to.file, "w/src/main.c");
+    }
+
+    #[test]
+    fn lenient_mode_keeps_a_lone_mnemonic_like_directory() {
+        let input = "diff --git a/w/main.c b/w/main.c\nindex 1111111..2222222 100644\n\
+                     --- a/w/main.c\n+++ b/w/main.c\n@@ -1 +1 @@\n-a\n+b\n\
+                     diff --git w/main.c w/main.c\nindex 1111111..2222222 100644\n\
+                     --- w/main.c\n+++ w/main.c\n@@ -1 +1 @@\n-a\n+b\n";
+        let diffs = crate::parse_with_config(input, lenient()).unwrap();
+        let paths: Vec<_> = diffs.iter().map(|diff| diff.to.file.as_str()).collect();
+        assert_eq!(paths, ["w/main.c", "w/main.c"]);
+    }
+
+    #[test]
+    fn no_prefix_headers() {
+        let config = ParserConfig {
+            src_prefix: String::new(),
+            dst_prefix: String::new(),
+            ..ParserConfig::default()
+        };
+        let input = "diff --git src/lib.rs src/lib.rs\nindex 1111111..2222222 100644\n\
+                     --- src/lib.rs\n+++ src/lib.rs\n@@ -1 +1 @@\n-a\n+b\n\
+                     diff --git new.txt new.txt\nnew file mode 100644\nindex 0000000..3333333\n\
+                     --- /dev/null\n+++ new.txt\n@@ -0,0 +1 @@\n+c\n";
+        let diffs = crate::parse_with_config(input, config).unwrap();
+        assert_eq!(diffs.len(), 2);
+        assert_eq!(diffs[0].from.file, "src/lib.rs");
+        assert_eq!(diffs[0].to.file, "src/lib.rs");
+        assert_eq!(diffs[0].change_type(), ChangeType::Modified);
+        assert_eq!(diffs[1].to.file, "new.txt");
+        assert!(diffs[1].from.is_dev_null);
+        assert_eq!(diffs[1].change_type(), ChangeType::Added);
+
+        // The default `a/` and `b/` are optional, so the default config agrees.
+        assert_eq!(crate::parse(input).unwrap(), diffs);
     }
-    Ok(parses)
 }
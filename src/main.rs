@@ -1,25 +1,158 @@
 use std::env;
-use std::fs::File;
-use std::io::{self, BufRead};
+use std::error::Error;
+use std::fs;
+use std::io::{self, Read};
+use std::process;
 
-use gitdiffparser::aggregator;
-use gitdiffparser::line_parser;
+use gitdiffparser::aggregator::{ChangeType, FileDiff};
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
+const USAGE: &str =
+    "usage: gitdiffparser [--json | --stat | --name-only | --name-status] [--path <glob>] [<diff-file> | -]";
 
-    let file = File::open(&args[1]).unwrap();
-    let lines = io::BufReader::new(file).lines().map(|l| l.unwrap());
+enum Format {
+    Summary,
+    Json,
+    Stat,
+    NameOnly,
+    NameStatus,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut format = Format::Summary;
+    let mut pattern = None;
+    let mut path = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => format = Format::Json,
+            "--stat" => format = Format::Stat,
+            "--name-only" => format = Format::NameOnly,
+            "--name-status" => format = Format::NameStatus,
+            "--path" => pattern = Some(args.next().unwrap_or_else(|| usage())),
+            _ if path.is_none() && (arg == "-" || !arg.starts_with('-')) => path = Some(arg),
+            _ => usage(),
+        }
+    }
+    // Without a path, or with "-", the diff is read from stdin: `git diff | gitdiffparser`.
+    let input = match path.as_deref() {
+        None | Some("-") => {
+            let mut input = String::new();
+            io::stdin().lock().read_to_string(&mut input)?;
+            input
+        }
+        Some(path) => fs::read_to_string(path)?,
+    };
+    let mut diffs = gitdiffparser::parse(&input)?;
+    if let Some(pattern) = pattern {
+        diffs = gitdiffparser::filter_by_path(diffs, &pattern);
+    }
+    match format {
+        Format::Summary => print_summary(&diffs),
+        Format::Json => print_json(&diffs)?,
+        Format::Stat => print_stat(&diffs),
+        Format::NameOnly => {
+            for diff in &diffs {
+                println!("{}", diff.to.file);
+            }
+        }
+        Format::NameStatus => print_name_status(&diffs),
+    }
+    Ok(())
+}
 
-    /*
-    for line in lines {
-        println!("{:?}", line);
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+fn print_summary(diffs: &[FileDiff]) {
+    for diff in diffs {
+        match diff.change_type() {
+            change_type @ (ChangeType::Renamed | ChangeType::Copied) => {
+                println!("{:?}\t{} -> {}", change_type, diff.from.file, diff.to.file)
+            }
+            change_type => println!("{:?}\t{}", change_type, diff.to.file),
+        }
+    }
+}
+
+// Like `git diff --name-status`, which shows mode changes as modifications.
+fn print_name_status(diffs: &[FileDiff]) {
+    for diff in diffs {
+        let status = match diff.change_type() {
+            ChangeType::Added => 'A',
+            ChangeType::Deleted => 'D',
+            ChangeType::Modified | ChangeType::ModeChanged => 'M',
+            ChangeType::Renamed => 'R',
+            ChangeType::Copied => 'C',
+        };
+        match diff.change_type() {
+            ChangeType::Renamed | ChangeType::Copied => {
+                // Git pads the similarity score to three digits: "R096".
+                let score = diff
+                    .similarity()
+                    .map_or(String::new(), |similarity| format!("{:03}", similarity));
+                println!("{}{}\t{}\t{}", status, score, diff.from.file, diff.to.file)
+            }
+            _ => println!("{}\t{}", status, diff.to.file),
+        }
     }
-    */
+}
+
+fn plural(n: usize, singular: &str, plural: &str) -> String {
+    format!("{} {}", n, if n == 1 { singular } else { plural })
+}
+
+// Like `git diff --stat`, without the graph scaling.
+fn print_stat(diffs: &[FileDiff]) {
+    let paths: Vec<String> = diffs
+        .iter()
+        .map(|diff| match diff.change_type() {
+            ChangeType::Renamed | ChangeType::Copied => {
+                format!("{} => {}", diff.from.file, diff.to.file)
+            }
+            _ => diff.to.file.to_string(),
+        })
+        .collect();
+    let width = paths
+        .iter()
+        .map(|path| path.chars().count())
+        .max()
+        .unwrap_or(0);
+
+    for (diff, path) in diffs.iter().zip(&paths) {
+        if diff.is_binary {
+            println!(" {:<width$} | Bin", path, width = width);
+        } else {
+            let (additions, deletions) = diff.stats();
+            println!(
+                " {:<width$} | +{} -{}",
+                path,
+                additions,
+                deletions,
+                width = width
+            );
+        }
+    }
+
+    let (additions, deletions) = gitdiffparser::total_stats(diffs);
+    let mut summary = vec![plural(diffs.len(), "file changed", "files changed")];
+    if additions > 0 || deletions == 0 {
+        summary.push(plural(additions, "insertion(+)", "insertions(+)"));
+    }
+    if deletions > 0 || additions == 0 {
+        summary.push(plural(deletions, "deletion(-)", "deletions(-)"));
+    }
+    println!(" {}", summary.join(", "));
+}
+
+#[cfg(feature = "json")]
+fn print_json(diffs: &[FileDiff]) -> Result<(), Box<dyn Error>> {
+    println!("{}", serde_json::to_string_pretty(diffs)?);
+    Ok(())
+}
 
-    let lines = line_parser::parse_lines(lines).unwrap();
-    //println!("{:?}", lines.len());
-    //println!("{:?}", lines[0]);
-    let x = aggregator::aggregator(&lines);
-    println!("{:?}", x.len());
+#[cfg(not(feature = "json"))]
+fn print_json(_diffs: &[FileDiff]) -> Result<(), Box<dyn Error>> {
+    Err("JSON output needs the \"json\" feature".into())
 }
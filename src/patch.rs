@@ -0,0 +1,161 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::aggregator::FileDiff;
+use crate::line_parser::ParseError;
+use crate::DiffParser;
+
+/// Mail headers and commit message of a `git format-patch` email.
+#[derive(Debug, Default)]
+pub struct PatchMeta {
+    pub author: Option<String>,
+    pub subject: Option<String>,
+    pub date: Option<String>,
+    pub body: String,
+}
+
+fn is_diff_start(line: &str) -> bool {
+    line.starts_with("diff --git ")
+        || line.starts_with("diff --cc ")
+        || line.starts_with("diff --combined ")
+}
+
+// Index of the "-- " line opening the trailing signature, if a signature (git
+// puts its version there) follows it and the lines after it can't be part of a
+// diff. A last hunk line deleting "- " reads as "-- " too, but nothing follows it.
+fn signature_start(lines: &[&str]) -> Option<usize> {
+    let idx = lines.iter().rposition(|line| *line == "-- ")?;
+    let rest = &lines[idx + 1..];
+    let is_signature = rest.iter().any(|line| !line.is_empty())
+        && rest
+            .iter()
+            .all(|line| !line.starts_with(['-', '+', ' ', '@', '\\']) && !is_diff_start(line));
+    if is_signature {
+        Some(idx)
+    } else {
+        None
+    }
+}
+
+// Shifts an error's line number from the diff part to the whole email.
+fn offset_error(err: ParseError, offset: usize) -> ParseError {
+    match err {
+        ParseError::Unexpected {
+            line,
+            expected,
+            found,
+        } => ParseError::Unexpected {
+            line: line + offset,
+            expected,
+            found,
+        },
+        ParseError::AggregateError(n, err) => ParseError::AggregateError(n + offset, err),
+        ParseError::Utf8(n, err) => ParseError::Utf8(n + offset, err),
+        #[cfg(feature = "std")]
+        err @ ParseError::Io(_) => err,
+    }
+}
+
+fn parse_meta(lines: &[&str]) -> PatchMeta {
+    let mut meta = PatchMeta::default();
+    let mut lines = lines.iter();
+
+    // "From {SHA} {DATE}", then RFC 822 headers up to the first blank line.
+    let mut last_header: Option<&mut String> = None;
+    for line in lines.by_ref() {
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with([' ', '\t']) {
+            // A folded continuation of the previous header.
+            if let Some(value) = last_header {
+                value.push_str(line);
+                last_header = Some(value);
+            }
+            continue;
+        }
+        last_header = None;
+        if let Some((name, value)) = line.split_once(": ") {
+            let field = match name {
+                "From" => &mut meta.author,
+                "Subject" => &mut meta.subject,
+                "Date" => &mut meta.date,
+                _ => continue,
+            };
+            last_header = Some(field.insert(value.to_string()));
+        }
+    }
+
+    // The commit message runs up to the "---" before the diffstat.
+    let body: Vec<&str> = lines.take_while(|line| **line != "---").copied().collect();
+    meta.body = body.join("\n").trim_end().to_string();
+    meta
+}
+
+/// Parses a `git format-patch` email, returning its headers and commit message
+/// along with the diff. Input without mail headers yields an empty `PatchMeta`.
+pub fn parse_patch(input: &str) -> Result<(PatchMeta, Vec<FileDiff>), ParseError> {
+    let lines: Vec<&str> = input.lines().collect();
+    let diff_start = lines
+        .iter()
+        .position(|line| is_diff_start(line))
+        .unwrap_or(lines.len());
+    let diff_end =
+        signature_start(&lines[diff_start..]).map_or(lines.len(), |idx| diff_start + idx);
+
+    let meta = parse_meta(&lines[..diff_start]);
+    let mut files = DiffParser::new(lines[diff_start..diff_end].iter())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| offset_error(err, diff_start))?;
+    for file in &mut files {
+        file.source_line += diff_start;
+    }
+    Ok((meta, files))
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::format;
+
+    use super::*;
+
+    const HEADER: &str = "diff --git a/f b/f\nindex 1111111..2222222 100644\n--- a/f\n+++ b/f\n";
+
+    #[test]
+    fn strips_the_mail_signature() {
+        let input = format!("{HEADER}@@ -1 +1 @@\n-a\n+b\n-- \n2.39.0\n\n");
+        let (_, files) = parse_patch(&input).unwrap();
+        assert_eq!(files[0].chunks[0].lines.len(), 2);
+    }
+
+    #[test]
+    fn keeps_a_last_line_deleting_a_dash() {
+        let input = format!("{HEADER}@@ -1,2 +1 @@\n a\n-- \n");
+        let (_, files) = parse_patch(&input).unwrap();
+        assert_eq!(files[0].chunks[0].lines.len(), 2);
+    }
+
+    #[test]
+    fn folded_subject_and_no_signature() {
+        let input = format!(
+            "From 1234567890abcdef1234567890abcdef12345678 Mon Sep 17 00:00:00 2001\n\
+             From: Jane Doe <jane@example.com>\n\
+             Date: Tue, 3 Jan 2023 10:00:00 +0000\n\
+             Subject: [PATCH] Fix the parser when a subject line is long enough\n to be folded\n\
+             \n\
+             Explain the fix.\n\
+             ---\n f | 2 +-\n 1 file changed, 1 insertion(+), 1 deletion(-)\n\n\
+             {HEADER}@@ -1 +1 @@\n-a\n+b\n"
+        );
+        let (meta, files) = parse_patch(&input).unwrap();
+        assert_eq!(
+            meta.subject.as_deref(),
+            Some("[PATCH] Fix the parser when a subject line is long enough to be folded")
+        );
+        assert_eq!(meta.author.as_deref(), Some("Jane Doe <jane@example.com>"));
+        assert_eq!(meta.body, "Explain the fix.");
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].source_line, 12);
+        assert_eq!(files[0].chunks[0].lines.len(), 2);
+    }
+}
@@ -0,0 +1,130 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use regex::Regex;
+
+use crate::aggregator::{BlobId, FileMode};
+use crate::lazy::Lazy;
+use crate::line_parser::{unquote_path, ExpectedLine, ParseError};
+
+static RAW_ENTRY: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^:(?P<old_mode>[0-7]{6}) (?P<new_mode>[0-7]{6}) (?P<old_blob>[0-9a-f]+)(?:\.\.\.)? (?P<new_blob>[0-9a-f]+)(?:\.\.\.)? (?P<status>[A-Z])(?P<score>\d*)\t(?P<from>[^\t]+)(?:\t(?P<to>[^\t]+))?$").unwrap()
+});
+
+/// One line of `git diff --raw`, such as `:100644 100755 abc1234 def5678 M\tfile.c`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RawDiffEntry {
+    // "000000" on the missing side of an add or delete, like `FileDiffPoint::mode`.
+    pub old_mode: String,
+    pub new_mode: String,
+    pub old_blob: String,
+    pub new_blob: String,
+    // A, C, D, M, R, T, U or X.
+    pub status: char,
+    // The similarity of a copy or rename, or the dissimilarity of a rewrite.
+    pub score: Option<u8>,
+    pub from: String,
+    // Same as `from` unless the file was renamed or copied.
+    pub to: String,
+}
+
+impl RawDiffEntry {
+    /// The old blob, or `None` when the file was added.
+    pub fn old_blob_id(&self) -> Option<BlobId> {
+        BlobId::parse(&self.old_blob)
+    }
+
+    /// The new blob, or `None` when the file was deleted.
+    pub fn new_blob_id(&self) -> Option<BlobId> {
+        BlobId::parse(&self.new_blob)
+    }
+
+    /// The old mode, or `None` when the file was added.
+    pub fn old_file_mode(&self) -> Option<FileMode> {
+        FileMode::parse(&self.old_mode)
+    }
+
+    /// The new mode, or `None` when the file was deleted.
+    pub fn new_file_mode(&self) -> Option<FileMode> {
+        FileMode::parse(&self.new_mode)
+    }
+}
+
+fn parse_entry(line: &str) -> Option<RawDiffEntry> {
+    let captures = RAW_ENTRY.captures(line)?;
+    let field = |name: &str| captures.name(name).map(|m| m.as_str().to_string());
+    let from = unquote_path(&field("from")?);
+    Some(RawDiffEntry {
+        old_mode: field("old_mode")?,
+        new_mode: field("new_mode")?,
+        old_blob: field("old_blob")?,
+        new_blob: field("new_blob")?,
+        status: field("status")?.chars().next()?,
+        score: field("score").and_then(|score| score.parse().ok()),
+        to: field("to").map_or_else(|| from.clone(), |to| unquote_path(&to)),
+        from,
+    })
+}
+
+/// Parses the output of `git diff --raw`, which lists each changed file's modes,
+/// blobs and status without any hunks.
+pub fn parse_raw(input: &str) -> Result<Vec<RawDiffEntry>, ParseError> {
+    input
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(idx, line)| {
+            parse_entry(line).ok_or_else(|| ParseError::Unexpected {
+                line: idx + 1,
+                expected: ExpectedLine::RawDiffEntry,
+                found: line.to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modify_rename_and_copy_entries() {
+        let input = ":100644 100644 bcd1234 0123456 M\tfile0\n\
+                     :100644 100644 abcd123 1234567 R100\told name.txt\tnew name.txt\n\
+                     :100644 100644 abcd123 1234567 C75\tsrc.c\tcopy.c\n";
+        let entries = parse_raw(input).unwrap();
+        let summary: Vec<_> = entries
+            .iter()
+            .map(|entry| {
+                (
+                    entry.status,
+                    entry.score,
+                    entry.from.as_str(),
+                    entry.to.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ('M', None, "file0", "file0"),
+                ('R', Some(100), "old name.txt", "new name.txt"),
+                ('C', Some(75), "src.c", "copy.c"),
+            ]
+        );
+        assert_eq!(entries[0].old_blob, "bcd1234");
+        assert_eq!(entries[0].new_mode, "100644");
+    }
+
+    #[test]
+    fn malformed_entry() {
+        let input = ":100644 100644 bcd1234 0123456 M\tfile0\nnot a raw line\n";
+        assert!(matches!(
+            parse_raw(input),
+            Err(ParseError::Unexpected {
+                line: 2,
+                expected: ExpectedLine::RawDiffEntry,
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,134 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::str;
+
+use crate::aggregate;
+use crate::aggregator::{Aggregator, FileDiff};
+use crate::line_parser::{Grammar, LineMachine, ParseError, ParserConfig};
+
+/// The push-based counterpart of [`DiffParser`](crate::DiffParser): takes the
+/// diff in chunks of any size, buffering a partial last line until its `\n`
+/// arrives, and returns each `FileDiff` as soon as the next one starts.
+pub struct DiffScanner {
+    // Bytes of a line whose "\n" hasn't been pushed yet.
+    partial: Vec<u8>,
+    machine: LineMachine,
+    aggregator: Aggregator,
+    // Set by an error: the rest of the diff is ignored until `finish`.
+    done: bool,
+}
+
+impl Default for DiffScanner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DiffScanner {
+    pub fn new() -> Self {
+        Self::with_config(ParserConfig::default())
+    }
+
+    pub fn with_config(config: ParserConfig) -> Self {
+        DiffScanner {
+            partial: vec![],
+            machine: LineMachine::new(Grammar::new(config)),
+            aggregator: Aggregator::new(),
+            done: false,
+        }
+    }
+
+    /// Parses every line completed by `bytes`, returning the files that ended.
+    /// After an error, the rest of the diff is ignored until [`finish`](Self::finish).
+    pub fn push(&mut self, bytes: &[u8]) -> Result<Vec<FileDiff>, ParseError> {
+        if self.done {
+            return Ok(vec![]);
+        }
+        self.partial.extend_from_slice(bytes);
+        let mut diffs = vec![];
+        let mut start = 0;
+        while let Some(len) = self.partial[start..].iter().position(|&b| b == b'\n') {
+            let end = start + len;
+            let line = &self.partial[start..end];
+            match step(&mut self.machine, &mut self.aggregator, line) {
+                Ok(file_diff) => diffs.extend(file_diff),
+                Err(err) => {
+                    self.done = true;
+                    self.partial.clear();
+                    return Err(err);
+                }
+            }
+            start = end + 1;
+        }
+        self.partial.drain(..start);
+        Ok(diffs)
+    }
+
+    /// Parses a last line without a `\n` and returns the files still open. The
+    /// scanner starts over afterwards, ready for another diff.
+    pub fn finish(&mut self) -> Result<Vec<FileDiff>, ParseError> {
+        let partial = core::mem::take(&mut self.partial);
+        let done = core::mem::take(&mut self.done);
+        let mut diffs = vec![];
+        let last = if partial.is_empty() || done {
+            Ok(None)
+        } else {
+            step(&mut self.machine, &mut self.aggregator, &partial)
+        };
+        let open = self.aggregator.finish();
+        self.machine.reset();
+        if done {
+            return Ok(diffs);
+        }
+        diffs.extend(last?);
+        diffs.extend(open);
+        Ok(diffs)
+    }
+}
+
+fn step(
+    machine: &mut LineMachine,
+    aggregator: &mut Aggregator,
+    line: &[u8],
+) -> Result<Option<FileDiff>, ParseError> {
+    // Counted before decoding, so the error names the line it's about.
+    let line_number = machine.line_number() + 1;
+    let line = str::from_utf8(line).map_err(|err| ParseError::Utf8(line_number, err))?;
+    match machine.step(line)? {
+        Some(kind) => aggregate(aggregator, &kind, machine.line_number()),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT: &str = "diff --git a/é b/é\nindex 1111111..2222222 100644\n--- a/é\n+++ b/é\n\
+                         @@ -1 +1 @@\n-ä\n+ö\ndiff --git a/f b/f\nindex 1111111..2222222 100644\n\
+                         --- a/f\n+++ b/f\n@@ -1 +1 @@\n-a\n+b";
+
+    #[test]
+    fn matches_parse_at_any_split() {
+        let expected = crate::parse(INPUT).unwrap();
+        for size in 1..INPUT.len() {
+            let mut scanner = DiffScanner::new();
+            let mut diffs = vec![];
+            for bytes in INPUT.as_bytes().chunks(size) {
+                diffs.extend(scanner.push(bytes).unwrap());
+            }
+            diffs.extend(scanner.finish().unwrap());
+            assert_eq!(diffs, expected, "chunks of {size} bytes");
+        }
+    }
+
+    #[test]
+    fn reports_an_error_once() {
+        let mut scanner = DiffScanner::new();
+        assert!(scanner.push(b"diff --git a/f b/f\nbogus\n").is_err());
+        assert!(scanner.push(b"more\nlines\n").unwrap().is_empty());
+        assert!(scanner.finish().unwrap().is_empty());
+        assert_eq!(scanner.push(INPUT.as_bytes()).unwrap().len(), 1);
+        assert_eq!(scanner.finish().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,114 @@
+use std::io;
+use std::mem;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use futures_core::Stream;
+use tokio::io::AsyncBufRead;
+
+use crate::aggregate;
+use crate::aggregator::{Aggregator, FileDiff};
+use crate::line_parser::{Grammar, LineMachine, ParseError, ParserConfig};
+
+/// The async counterpart of [`DiffParser`](crate::DiffParser): reads lines from
+/// an `AsyncBufRead` and yields each `FileDiff` as soon as the next one starts,
+/// without buffering the whole diff. Lines end only at `\n`, as in [`parse`](crate::parse),
+/// so `\r\n` endings set `trailing_cr` and `ParserConfig::preserve_cr` applies.
+pub struct DiffStream<R> {
+    reader: R,
+    // The line being read, up to but not including its "\n".
+    line: Vec<u8>,
+    machine: LineMachine,
+    aggregator: Aggregator,
+    done: bool,
+}
+
+impl<R: AsyncBufRead + Unpin> DiffStream<R> {
+    pub fn new(reader: R) -> Self {
+        Self::with_config(reader, ParserConfig::default())
+    }
+
+    pub fn with_config(reader: R, config: ParserConfig) -> Self {
+        DiffStream {
+            reader,
+            line: Vec::new(),
+            machine: LineMachine::new(Grammar::new(config)),
+            aggregator: Aggregator::new(),
+            done: false,
+        }
+    }
+
+    // Like `read_until(b'\n')` without the "\n", which tokio's `lines()` would
+    // also take the "\r" of. `None` at the end of the input.
+    fn poll_line(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Option<Vec<u8>>>> {
+        loop {
+            let available = ready!(Pin::new(&mut self.reader).poll_fill_buf(cx))?;
+            if available.is_empty() {
+                let line = mem::take(&mut self.line);
+                return Poll::Ready(Ok((!line.is_empty()).then_some(line)));
+            }
+            let (used, ended) = match available.iter().position(|&b| b == b'\n') {
+                Some(end) => {
+                    self.line.extend_from_slice(&available[..end]);
+                    (end + 1, true)
+                }
+                None => {
+                    self.line.extend_from_slice(available);
+                    (available.len(), false)
+                }
+            };
+            Pin::new(&mut self.reader).consume(used);
+            if ended {
+                return Poll::Ready(Ok(Some(mem::take(&mut self.line))));
+            }
+        }
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> Stream for DiffStream<R> {
+    type Item = Result<FileDiff, ParseError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.done {
+            return Poll::Ready(None);
+        }
+
+        loop {
+            let line = match ready!(this.poll_line(cx)) {
+                Ok(Some(line)) => line,
+                Ok(None) => {
+                    this.done = true;
+                    return Poll::Ready(this.aggregator.finish().map(Ok));
+                }
+                Err(err) => {
+                    this.done = true;
+                    return Poll::Ready(Some(Err(ParseError::Io(err))));
+                }
+            };
+            let line = match String::from_utf8(line) {
+                Ok(line) => line,
+                Err(err) => {
+                    this.done = true;
+                    let line_number = this.machine.line_number() + 1;
+                    return Poll::Ready(Some(Err(ParseError::Utf8(line_number, err.utf8_error()))));
+                }
+            };
+            let pushed = match this.machine.step(&line) {
+                Ok(Some(kind)) => {
+                    aggregate(&mut this.aggregator, &kind, this.machine.line_number())
+                }
+                Ok(None) => Ok(None),
+                Err(err) => Err(err),
+            };
+            match pushed {
+                Ok(Some(file_diff)) => return Poll::Ready(Some(Ok(file_diff))),
+                Ok(None) => {}
+                Err(err) => {
+                    this.done = true;
+                    return Poll::Ready(Some(Err(err)));
+                }
+            }
+        }
+    }
+}
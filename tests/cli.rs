@@ -0,0 +1,56 @@
+#![cfg(feature = "std")]
+
+use std::process::Command;
+
+const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/git.diff");
+
+fn run(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_gitdiffparser"))
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn stat() {
+    assert_eq!(
+        run(&["--stat", FIXTURE]),
+        " added.txt          | +1 -0\n \
+         bin.dat            | Bin\n \
+         main.rs            | +1 -1\n \
+         mod.sh             | +0 -0\n \
+         old.txt => new.txt | +0 -0\n \
+         nonl.txt           | +1 -1\n \
+         nums.txt           | +2 -2\n \
+         7 files changed, 5 insertions(+), 4 deletions(-)\n"
+    );
+}
+
+#[test]
+fn name_only() {
+    assert_eq!(
+        run(&["--name-only", FIXTURE]),
+        "added.txt\nbin.dat\nmain.rs\nmod.sh\nnew.txt\nnonl.txt\nnums.txt\n"
+    );
+}
+
+#[test]
+fn name_status() {
+    assert_eq!(
+        run(&["--name-status", FIXTURE]),
+        "A\tadded.txt\nA\tbin.dat\nM\tmain.rs\nM\tmod.sh\nR100\told.txt\tnew.txt\n\
+         M\tnonl.txt\nM\tnums.txt\n"
+    );
+}
+
+#[test]
+fn unknown_option() {
+    let output = Command::new(env!("CARGO_BIN_EXE_gitdiffparser"))
+        .arg("--bogus")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("usage:"));
+}
@@ -0,0 +1,77 @@
+use gitdiffparser::aggregator::{ChangeType, DiffAction};
+use gitdiffparser::parse;
+
+#[test]
+fn symlink_targets() {
+    let diffs = parse(include_str!("fixtures/symlink.diff")).unwrap();
+    assert_eq!(diffs.len(), 2);
+
+    assert!(diffs[0].is_symlink);
+    assert_eq!(diffs[0].change_type(), ChangeType::Modified);
+    assert_eq!(diffs[0].from.symlink_target.as_deref(), Some("target.txt"));
+    assert_eq!(diffs[0].to.symlink_target.as_deref(), Some("other.txt"));
+
+    assert!(diffs[1].is_symlink);
+    assert_eq!(diffs[1].change_type(), ChangeType::Added);
+    assert_eq!(diffs[1].from.symlink_target, None);
+    assert_eq!(diffs[1].to.symlink_target.as_deref(), Some("dir/file.txt"));
+}
+
+#[test]
+fn submodule_commits() {
+    let diffs = parse(include_str!("fixtures/submodule.diff")).unwrap();
+    assert_eq!(diffs.len(), 2);
+
+    assert!(diffs[0].is_submodule);
+    assert_eq!(diffs[0].change_type(), ChangeType::Modified);
+    assert_eq!(
+        diffs[0].from.submodule_commit.as_deref(),
+        Some("b82e927375294da9ac3ca37c961024d314e7c338")
+    );
+    assert_eq!(
+        diffs[0].to.submodule_commit.as_deref(),
+        Some("5d8b33a93f0ebfff1690d4281ec09c52dbb50516")
+    );
+
+    assert!(diffs[1].is_submodule);
+    assert_eq!(diffs[1].change_type(), ChangeType::Deleted);
+    assert_eq!(diffs[1].from.file, "vendor/old");
+    assert_eq!(diffs[1].to.submodule_commit, None);
+}
+
+#[test]
+fn combined_diff_parent_actions() {
+    use DiffAction::{Add, Context, Delete};
+
+    let diffs = parse(include_str!("fixtures/combined.diff")).unwrap();
+    assert_eq!(diffs.len(), 1);
+    assert_eq!(diffs[0].to.file, "f.txt");
+    let chunk = &diffs[0].chunks[0];
+    assert_eq!(chunk.parents.len(), 2);
+    assert_eq!(
+        (chunk.parents[1].line_start, chunk.parents[1].line_count),
+        (1, 6)
+    );
+    assert_eq!((chunk.to.line_start, chunk.to.line_count), (1, 7));
+
+    let lines: Vec<_> = chunk
+        .lines
+        .iter()
+        .skip(2)
+        .take(4)
+        .map(|line| (line.line.as_str(), &line.action, &line.parent_actions[..]))
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            ("three-main", &Delete, &[Delete, Context][..]),
+            ("three-side", &Delete, &[Context, Delete][..]),
+            ("three-merged", &Add, &[Add, Add][..]),
+            ("extra", &Add, &[Add, Add][..]),
+        ]
+    );
+    assert!(chunk.lines[0]
+        .parent_actions
+        .iter()
+        .all(|action| *action == Context));
+}
@@ -0,0 +1,81 @@
+use gitdiffparser::aggregator::FileDiff;
+use gitdiffparser::line_parser::ParserConfig;
+use gitdiffparser::parse_with_config;
+
+const FIXTURES: &[(&str, &str)] = &[
+    ("git.diff", include_str!("fixtures/git.diff")),
+    ("crlf.diff", include_str!("fixtures/crlf.diff")),
+    ("copy.diff", include_str!("fixtures/copy.diff")),
+    (
+        "binary_patch.diff",
+        include_str!("fixtures/binary_patch.diff"),
+    ),
+    ("combined.diff", include_str!("fixtures/combined.diff")),
+    ("no_newline.diff", include_str!("fixtures/no_newline.diff")),
+    ("plain.diff", include_str!("fixtures/plain.diff")),
+    ("timestamps.diff", include_str!("fixtures/timestamps.diff")),
+    ("symlink.diff", include_str!("fixtures/symlink.diff")),
+    ("submodule.diff", include_str!("fixtures/submodule.diff")),
+];
+
+fn prefixes(src: &str, dst: &str) -> ParserConfig {
+    ParserConfig {
+        src_prefix: src.to_string(),
+        dst_prefix: dst.to_string(),
+        ..ParserConfig::default()
+    }
+}
+
+// Display output always uses the a/ b/ prefixes, so it is re-parsed with the
+// default prefixes whatever the original config was.
+fn assert_round_trips(name: &str, input: &str, config: ParserConfig) {
+    let reparse_config = ParserConfig {
+        preserve_cr: config.preserve_cr,
+        ..ParserConfig::default()
+    };
+    let parsed = parse_with_config(input, config).unwrap();
+    let output: String = parsed.iter().map(|diff| diff.to_string()).collect();
+    let reparsed = parse_with_config(&output, reparse_config)
+        .unwrap_or_else(|err| panic!("{}: {:?} in\n{}", name, err, output));
+
+    let strip = |diffs: Vec<FileDiff>| -> Vec<FileDiff> {
+        diffs
+            .into_iter()
+            .map(|diff| FileDiff {
+                source_line: 0,
+                ..diff
+            })
+            .collect()
+    };
+    assert_eq!(strip(parsed), strip(reparsed), "{}:\n{}", name, output);
+}
+
+#[test]
+fn fixtures_round_trip_through_display() {
+    for (name, input) in FIXTURES {
+        assert_round_trips(name, input, ParserConfig::default());
+    }
+}
+
+#[test]
+fn crlf_content_round_trips_with_preserve_cr() {
+    let config = ParserConfig {
+        preserve_cr: true,
+        ..ParserConfig::default()
+    };
+    assert_round_trips("crlf.diff", include_str!("fixtures/crlf.diff"), config);
+}
+
+#[test]
+fn other_prefixes_round_trip_as_a_b() {
+    assert_round_trips(
+        "no_prefix.diff",
+        include_str!("fixtures/no_prefix.diff"),
+        prefixes("", ""),
+    );
+    assert_round_trips(
+        "custom_prefix.diff",
+        include_str!("fixtures/custom_prefix.diff"),
+        prefixes("old/", "new/"),
+    );
+}
@@ -0,0 +1,54 @@
+#![cfg(feature = "tokio")]
+
+use std::future::poll_fn;
+use std::pin::Pin;
+
+use futures_core::Stream;
+use gitdiffparser::line_parser::ParserConfig;
+use gitdiffparser::stream::DiffStream;
+use tokio::io::BufReader;
+
+const INPUT: &str = "diff --git a/f b/f\nindex 1111111..2222222 100644\n--- a/f\n+++ b/f\n\
+                     @@ -1 +1 @@\n-a\n+b\ndiff --git a/g b/g\nindex 3333333..4444444 100644\n\
+                     --- a/g\n+++ b/g\n@@ -1 +1 @@\n-c\n+d\n";
+
+#[tokio::test]
+async fn streams_the_same_files_as_parse() {
+    let diffs = collect(DiffStream::new(BufReader::new(INPUT.as_bytes()))).await;
+    assert_eq!(diffs, gitdiffparser::parse(INPUT).unwrap());
+}
+
+async fn collect<R: tokio::io::AsyncBufRead + Unpin>(
+    mut stream: DiffStream<R>,
+) -> Vec<gitdiffparser::aggregator::FileDiff> {
+    let mut diffs = vec![];
+    while let Some(diff) = poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await {
+        diffs.push(diff.unwrap());
+    }
+    diffs
+}
+
+#[tokio::test]
+async fn keeps_crlf_like_parse() {
+    let input = INPUT.replace('\n', "\r\n");
+    let config = || ParserConfig {
+        preserve_cr: true,
+        ..ParserConfig::default()
+    };
+    let reader = BufReader::new(input.as_bytes());
+    let diffs = collect(DiffStream::with_config(reader, config())).await;
+    assert_eq!(diffs.len(), 2);
+    assert_eq!(diffs[1].to.file, "g");
+    assert_eq!(diffs[1].chunks[0].lines[1].line, "d\r");
+    assert!(diffs[1].chunks[0].lines[1].trailing_cr);
+    assert_eq!(
+        diffs,
+        gitdiffparser::parse_with_config(&input, config()).unwrap()
+    );
+
+    // A tiny buffer splits lines, and the "\r\n" itself, across reads.
+    let reader = BufReader::with_capacity(3, input.as_bytes());
+    let default = collect(DiffStream::new(reader)).await;
+    assert_eq!(default, gitdiffparser::parse(&input).unwrap());
+    assert_eq!(default[0].chunks[0].lines[0].line, "a");
+}